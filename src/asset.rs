@@ -0,0 +1,285 @@
+// Asset kinds.
+//
+// An asset kind tells how the output will be used by VERA, so that the
+// rules of that use can be checked, and the output can be aligned as VERA
+// requires without giving '-a':
+//
+// * A tile set has 8 or 16 pixel tiles (8x8 by default), in tile order,
+//   at any bpp, and is aligned to 2048 bytes (a tile base).
+// * A tile map is virtual data (a map base), whose width and height are
+//   counts of tiles (32, 64, 128, or 256), and is aligned to 512 bytes. It
+//   may name its tile set, and is shown on layer 0, unless another is given.
+// * A sprite must be 8, 16, 32, or 64 pixels wide and high, at 4 or 8 bpp,
+//   and aligned to 32 bytes. Its width and height are given in the sprite
+//   attributes as 2-bit size codes.
+// * A bitmap must be 320 or 640 pixels wide (at most 240 or 480 lines), at
+//   any bpp, and is aligned to 2048 bytes (a tile base, which holds the
+//   bitmap address). It is shown on layer 0, unless another is given.
+// * A font is the same as '-f'.
+// * Raw data has no rules, and is aligned to 1 byte.
+//
+// Copyright 2023 by Curtis Whitley
+
+use crate::{DirParameters, FileParameters, Placement};
+
+pub const SPRITE_SIZES: [usize; 4] = [8, 16, 32, 64];
+pub const MAP_SIZES: [usize; 4] = [32, 64, 128, 256];
+pub const BITMAP_WIDTHS: [usize; 2] = [320, 640];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AssetKind {
+    Tileset,
+    Tilemap,
+    Sprite,
+    Bitmap,
+    Font,
+    Raw
+}
+
+impl AssetKind {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "ts" | "tileset" => Some(AssetKind::Tileset),
+            "tm" | "tilemap" => Some(AssetKind::Tilemap),
+            "sp" | "sprite" => Some(AssetKind::Sprite),
+            "bm" | "bitmap" => Some(AssetKind::Bitmap),
+            "font" => Some(AssetKind::Font),
+            "raw" => Some(AssetKind::Raw),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AssetKind::Tileset => "tileset",
+            AssetKind::Tilemap => "tilemap",
+            AssetKind::Sprite => "sprite",
+            AssetKind::Bitmap => "bitmap",
+            AssetKind::Font => "font",
+            AssetKind::Raw => "raw"
+        }
+    }
+
+    // The alignment that VERA requires for this kind of data.
+    pub fn alignment(&self) -> usize {
+        match self {
+            AssetKind::Tileset | AssetKind::Bitmap | AssetKind::Font => 2048,
+            AssetKind::Tilemap => 512,
+            AssetKind::Sprite => 32,
+            AssetKind::Raw => 1
+        }
+    }
+}
+
+// Check the options of a directory against the rules of its asset kind,
+// and fill in the tile size and alignment that the kind implies. The bits
+// per pixel must already have their default value.
+pub fn validate_kind(directory: &mut DirParameters) -> bool {
+    let kind = match directory.kind {
+        Some(kind) => kind,
+        None => {
+            if directory.pad || directory.layer.is_some() || directory.tileset.is_some() {
+                println!("ERROR: Padding (-pad), layer (-layer), and tile set (-tileset) are only used with an asset kind (-k)");
+                return false;
+            }
+            return true;
+        }
+    };
+    if directory.pad && kind != AssetKind::Sprite {
+        println!("ERROR: Padding (-pad) is only used with sprites (-k sprite)");
+        return false;
+    }
+    if directory.font && kind != AssetKind::Font {
+        println!("ERROR: Do not specify a font (-f) for a {}", kind.name());
+        return false;
+    }
+    if directory.layer.is_some() && kind != AssetKind::Bitmap && kind != AssetKind::Tilemap {
+        println!("ERROR: Layer (-layer) is only used with bitmaps and tile maps (-k bitmap or -k tilemap)");
+        return false;
+    }
+    if directory.tileset.is_some() && kind != AssetKind::Tilemap {
+        println!("ERROR: Tile set (-tileset) is only used with tile maps (-k tilemap)");
+        return false;
+    }
+    if directory.vapor && kind != AssetKind::Tilemap {
+        println!("ERROR: Do not specify a map base (-a mb) for a {}; use '-k tilemap'", kind.name());
+        return false;
+    }
+
+    match kind {
+        AssetKind::Tileset => {
+            if directory.tile_width == 0 {
+                directory.tile_width = 8;
+                directory.tile_height = 8;
+            }
+        },
+        AssetKind::Tilemap => {
+            if !MAP_SIZES.contains(&directory.width) || !MAP_SIZES.contains(&directory.height) {
+                println!("ERROR: A tile map must be 32, 64, 128, or 256 tiles wide and high (-w and -h), not {}x{}",
+                    directory.width, directory.height);
+                return false;
+            }
+            if directory.tile_width != 0 {
+                println!("ERROR: Do not specify tile size for a tile map");
+                return false;
+            }
+            directory.vapor = true;
+        },
+        AssetKind::Sprite => {
+            if directory.bpp != 4 && directory.bpp != 8 {
+                println!("ERROR: A sprite must use 4 or 8 bits-per-pixel");
+                return false;
+            }
+            if directory.tile_width != 0 {
+                println!("ERROR: Do not specify tile size for a sprite");
+                return false;
+            }
+        },
+        AssetKind::Bitmap => {
+            if directory.tile_width != 0 {
+                println!("ERROR: Do not specify tile size for a bitmap");
+                return false;
+            }
+        },
+        AssetKind::Font | AssetKind::Raw => {}
+    }
+
+    if directory.alignment == 0 {
+        directory.alignment = kind.alignment();
+    } else if !directory.alignment.is_multiple_of(kind.alignment()) {
+        println!("ERROR: A {} must be aligned to a multiple of {} bytes", kind.name(), kind.alignment());
+        return false;
+    }
+    true
+}
+
+// Check the output size of a file against the rules of its asset kind,
+// padding a sprite to the next legal size if asked.
+pub fn check_size(params: &mut FileParameters, pad: bool) -> bool {
+    match params.kind {
+        Some(AssetKind::Sprite) => {
+            let (width, height) = (params.width, params.height);
+            if pad {
+                match (padded_sprite_size(width), padded_sprite_size(height)) {
+                    (Some(padded_width), Some(padded_height)) => {
+                        params.width = padded_width;
+                        params.height = padded_height;
+                    },
+                    _ => {
+                        println!("ERROR: Sprite {} ({}x{}) is too large to pad to a sprite size (at most 64x64)",
+                            params.path, width, height);
+                        return false;
+                    }
+                }
+                if params.width != width || params.height != height {
+                    println!("Padded sprite {} from {}x{} to {}x{}.", params.path, width, height, params.width, params.height);
+                }
+            } else if sprite_size_code(width).is_none() || sprite_size_code(height).is_none() {
+                println!("ERROR: Sprite {} is {}x{}, but each side must be 8, 16, 32, or 64 pixels (or use -pad)",
+                    params.path, width, height);
+                return false;
+            }
+        },
+        Some(AssetKind::Bitmap) if !BITMAP_WIDTHS.contains(&params.width) => {
+            println!("ERROR: Bitmap {} is {} pixels wide, but must be 320 or 640 pixels wide",
+                params.path, params.width);
+            return false;
+        },
+        Some(AssetKind::Bitmap) if params.height > params.width * 3 / 4 => {
+            println!("ERROR: Bitmap {} is {} lines high, but a {} pixel wide bitmap may have at most {}",
+                params.path, params.height, params.width, params.width * 3 / 4);
+            return false;
+        },
+        _ => {}
+    }
+    true
+}
+
+// The 2-bit code for a sprite width or height, if the size is legal.
+pub fn sprite_size_code(size: usize) -> Option<u8> {
+    SPRITE_SIZES.iter().position(|legal| *legal == size).map(|code| code as u8)
+}
+
+// The smallest legal sprite width or height that holds the given size.
+pub fn padded_sprite_size(size: usize) -> Option<usize> {
+    SPRITE_SIZES.iter().find(|legal| **legal >= size).copied()
+}
+
+// Print the values to store in the sprite attributes for each sprite: the
+// address (bits 12:5 in byte 0, and bits 16:13 in byte 1 along with the
+// mode bit), and the height and width codes (bits 7:6 and 5:4 of byte 7,
+// along with the palette offset).
+pub fn print_sprite_attributes(sprites: &[FileParameters], layout: &[Placement]) {
+    println!("\nSprite Attributes\n");
+    println!("Start  Byte 0 Byte 1 Width      Height     Byte 7 Path/Name");
+    println!("------ ------ ------ ---------- ---------- ------ ----------------------------------");
+    for sprite in sprites {
+        let start = match layout.iter().find(|placement| placement.path == sprite.path) {
+            Some(placement) => placement.start,
+            None => continue
+        };
+        let (byte0, byte1, byte7) = sprite_attribute_bytes(start, sprite);
+        println!("${:05x}    ${:02x}    ${:02x} {:2} (code {}) {:2} (code {})    ${:02x} {}",
+            start, byte0, byte1,
+            sprite.width, sprite_size_code(sprite.width).unwrap(),
+            sprite.height, sprite_size_code(sprite.height).unwrap(),
+            byte7, sprite.path);
+    }
+    println!();
+}
+
+// Obtain the sprite attribute bytes that depend on the sprite data: byte 0
+// (address bits 12:5), byte 1 (mode, and address bits 16:13), and byte 7
+// (height, width, and palette offset).
+fn sprite_attribute_bytes(start: usize, sprite: &FileParameters) -> (u8, u8, u8) {
+    let width_code = sprite_size_code(sprite.width).unwrap();
+    let height_code = sprite_size_code(sprite.height).unwrap();
+    let mode: u8 = if sprite.bpp == 8 { 0x80 } else { 0 };
+    (((start >> 5) & 0xFF) as u8,
+        mode | ((start >> 13) & 0x0F) as u8,
+        (height_code << 6) | (width_code << 4) | sprite.palette_offset.unwrap_or(0) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_file;
+
+    fn sprite(width: usize, height: usize, bpp: u8, palette_offset: Option<usize>) -> FileParameters {
+        let mut file = test_file("sprite.png", width * height * bpp as usize / 8);
+        file.width = width;
+        file.height = height;
+        file.bpp = bpp;
+        file.palette_offset = palette_offset;
+        file
+    }
+
+    #[test]
+    fn every_sprite_size_has_a_code() {
+        for (code, size) in [(0, 8), (1, 16), (2, 32), (3, 64)] {
+            assert_eq!(sprite_size_code(size), Some(code));
+            assert_eq!(padded_sprite_size(size), Some(size));
+        }
+        for size in [0, 4, 12, 20, 48, 128] {
+            assert_eq!(sprite_size_code(size), None);
+        }
+    }
+
+    #[test]
+    fn sprites_are_padded_to_the_next_size() {
+        assert_eq!((padded_sprite_size(20), padded_sprite_size(12)), (Some(32), Some(16)));
+        assert_eq!(padded_sprite_size(1), Some(8));
+        assert_eq!(padded_sprite_size(9), Some(16));
+        assert_eq!(padded_sprite_size(33), Some(64));
+        assert_eq!(padded_sprite_size(65), None);
+    }
+
+    #[test]
+    fn attribute_bytes_hold_the_address_mode_and_sizes() {
+        // $1F000 >> 5 = $F80; bits 16:13 are $F.
+        assert_eq!(sprite_attribute_bytes(0x1F000, &sprite(64, 32, 8, None)), (0x80, 0x8F, 0xB0));
+        // $04020 >> 5 = $201; bits 16:13 are $2.
+        assert_eq!(sprite_attribute_bytes(0x04020, &sprite(16, 8, 4, Some(3))), (0x01, 0x02, 0x13));
+        assert_eq!(sprite_attribute_bytes(0, &sprite(8, 64, 4, Some(15))), (0x00, 0x00, 0xCF));
+    }
+}
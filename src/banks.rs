@@ -0,0 +1,148 @@
+// Banked RAM placement.
+//
+// Assets that do not fit into VRAM, or that are marked for banked RAM, can
+// be kept in the X16's banked high RAM (8 KB banks, seen at $A000-$BFFF)
+// and copied to VRAM when they are needed. They are packed one after
+// another, starting with the first bank; an asset that reaches the end of
+// a bank continues at the start of the next bank. Each bank is written as
+// its own file, and a table gives the first bank, address, and size of each
+// asset.
+//
+// Copyright 2023 by Curtis Whitley
+
+use crate::FileParameters;
+
+pub const BANK_SIZE: usize = 0x2000;
+pub const BANK_WINDOW: usize = 0xA000;
+pub const BANK_COUNT: usize = 256;
+pub const DEFAULT_FIRST_BANK: usize = 1;
+
+// Where one asset was placed in banked RAM.
+#[derive(Debug, Clone)]
+pub struct BankPlacement {
+    pub path: String,
+    pub bank: usize,
+    pub address: usize,
+    pub size: usize,
+    pub last_bank: usize
+}
+
+// The contents of one RAM bank.
+#[derive(Debug, Clone)]
+pub struct Bank {
+    pub number: usize,
+    pub data: Vec<u8>
+}
+
+// Pack the assets into banks, starting at the first bank.
+pub fn place_in_banks(files: &[FileParameters], first_bank: usize) -> Option<(Vec<BankPlacement>, Vec<Bank>)> {
+    let mut placements: Vec<BankPlacement> = vec![];
+    let mut banks: Vec<Bank> = vec![];
+    let mut offset: usize = 0; // from the start of the first bank
+    for file in files {
+        let last_offset = offset + file.binary.len().max(1) - 1;
+        let last_bank = first_bank + last_offset / BANK_SIZE;
+        if last_bank >= BANK_COUNT {
+            println!("ERROR: File {} does not fit in banked RAM (it would reach bank {})", file.path, last_bank);
+            return None;
+        }
+        placements.push(BankPlacement {
+            path: file.path.clone(),
+            bank: first_bank + offset / BANK_SIZE,
+            address: BANK_WINDOW + offset % BANK_SIZE,
+            size: file.binary.len(),
+            last_bank
+        });
+
+        // Split the data at the bank boundaries.
+        for byte in &file.binary {
+            let number = first_bank + offset / BANK_SIZE;
+            if banks.last().map(|bank| bank.number) != Some(number) {
+                banks.push(Bank { number, data: vec![] });
+            }
+            banks.last_mut().unwrap().data.push(*byte);
+            offset += 1;
+        }
+    }
+    Some((placements, banks))
+}
+
+// The name of the file for one bank, such as "BANK01.BIN".
+pub fn bank_filename(number: usize) -> String {
+    format!("BANK{:02}.BIN", number)
+}
+
+pub fn print_table(placements: &[BankPlacement]) {
+    println!("\nBanked RAM Arrangement\n");
+    println!("Bank Addr   Size  Path/Name");
+    println!("---- ----- ------ ----------------------------------");
+    for placement in placements {
+        let continues = if placement.last_bank != placement.bank {
+            format!(" (continues through bank {})", placement.last_bank)
+        } else {
+            String::new()
+        };
+        println!("{:4} ${:04x} {:6}  {}{}",
+            placement.bank, placement.address, placement.size, placement.path, continues);
+    }
+}
+
+// The table of assets, as ca65 source: for each asset, its first bank,
+// its address, and its size (3 bytes, as an asset may fill several banks).
+pub fn ca65_text(placements: &[BankPlacement]) -> String {
+    let mut text = String::new();
+    text.push_str("; Banked RAM assets: first bank, address ($A000-$BFFF), and size in bytes.\n");
+    text.push_str("begin_bank_table:\n");
+    for placement in placements {
+        text.push_str(&format!("    .byte    ${:02x}  ; {}\n", placement.bank, placement.path));
+        text.push_str(&format!("    .word    ${:04x}\n", placement.address));
+        text.push_str(&format!("    .faraddr ${:06x}\n", placement.size));
+    }
+    text.push_str("end_bank_table:\n");
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_file;
+
+    #[test]
+    fn small_files_share_a_bank() {
+        let (placements, banks) = place_in_banks(&[test_file("a", 100), test_file("b", 200)], DEFAULT_FIRST_BANK).unwrap();
+        assert_eq!((placements[0].bank, placements[0].address, placements[0].last_bank), (1, 0xA000, 1));
+        assert_eq!((placements[1].bank, placements[1].address, placements[1].last_bank), (1, 0xA064, 1));
+        assert_eq!(banks.len(), 1);
+        assert_eq!(banks[0].number, 1);
+        assert_eq!(banks[0].data.len(), 300);
+    }
+
+    #[test]
+    fn a_file_continues_into_the_next_bank() {
+        let (placements, banks) = place_in_banks(&[test_file("a", 0x1000), test_file("b", 0x1800)], 5).unwrap();
+        assert_eq!((placements[1].bank, placements[1].address, placements[1].last_bank), (5, 0xB000, 6));
+        assert_eq!(banks.iter().map(|bank| (bank.number, bank.data.len())).collect::<Vec<_>>(),
+            vec![(5, 0x2000), (6, 0x800)]);
+        assert_eq!(banks[1].data[0], (0x1000 % 256) as u8);
+    }
+
+    #[test]
+    fn a_full_bank_moves_the_next_file_to_a_new_bank() {
+        let (placements, banks) = place_in_banks(&[test_file("a", BANK_SIZE), test_file("b", 1)], 0).unwrap();
+        assert_eq!((placements[0].bank, placements[0].last_bank), (0, 0));
+        assert_eq!((placements[1].bank, placements[1].address), (1, BANK_WINDOW));
+        assert_eq!(banks.len(), 2);
+    }
+
+    #[test]
+    fn files_must_end_by_the_last_bank() {
+        assert!(place_in_banks(&[test_file("a", BANK_SIZE)], BANK_COUNT - 1).is_some());
+        assert!(place_in_banks(&[test_file("a", BANK_SIZE + 1)], BANK_COUNT - 1).is_none());
+    }
+
+    #[test]
+    fn bank_files_are_numbered() {
+        assert_eq!(bank_filename(1), "BANK01.BIN");
+        assert_eq!(bank_filename(255), "BANK255.BIN");
+    }
+}
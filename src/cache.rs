@@ -0,0 +1,294 @@
+// Incremental build cache.
+//
+// The cache remembers, for each input PNG file, a hash of its content and
+// of the parameters used to process it, along with the results of that
+// processing (its unique colors, whether it has transparent pixels, and
+// its output binary data). When a file and its parameters have not
+// changed, its colors can be reused without decoding the image again. Its
+// binary data can also be reused, provided that the palette indexes
+// assigned to its colors have not changed.
+//
+// Copyright 2023 by Curtis Whitley
+
+use std::collections::HashMap;
+use std::fs;
+use image::Rgb;
+
+const CACHE_MAGIC: &[u8; 4] = b"I2BC";
+const CACHE_VERSION: u32 = 2;
+
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub path: String,
+    pub content_hash: u64,
+    pub param_hash: u64,
+    pub width: usize,
+    pub height: usize,
+    pub colors: Vec<(Rgb<u8>, u8)>,
+    pub palette_hash: u64,
+    pub transparent: bool,
+    pub binary: Vec<u8>
+}
+
+#[derive(Debug)]
+pub struct Cache {
+    pub path: String,
+    pub entries: HashMap<String, CacheEntry>
+}
+
+impl Cache {
+    // Create an empty cache. An empty path means that the cache is only
+    // kept in memory, and is never written to a file.
+    pub fn new(path: &str) -> Self {
+        Cache {
+            path: path.to_string(),
+            entries: HashMap::new()
+        }
+    }
+
+    // Load the cache from a file. A missing or unreadable cache file
+    // simply results in an empty cache.
+    pub fn load(path: &str) -> Self {
+        let mut cache = Cache::new(path);
+        if let Ok(data) = fs::read(path) {
+            match decode_entries(&data) {
+                Some(entries) => {
+                    for entry in entries {
+                        cache.entries.insert(entry.path.clone(), entry);
+                    }
+                },
+                None => {
+                    println!("NOTE: Ignoring invalid cache file ({})", path);
+                }
+            }
+        }
+        cache
+    }
+
+    // Find the entry for a file, if its content and parameters are unchanged.
+    pub fn lookup(&self, path: &str, content_hash: u64, param_hash: u64) -> Option<&CacheEntry> {
+        match self.entries.get(path) {
+            Some(entry) => {
+                if entry.content_hash == content_hash && entry.param_hash == param_hash {
+                    Some(entry)
+                } else {
+                    None
+                }
+            },
+            None => None
+        }
+    }
+
+    pub fn store(&mut self, entry: CacheEntry) {
+        self.entries.insert(entry.path.clone(), entry);
+    }
+
+    // Encode all entries, in path order, so that an unchanged cache
+    // produces identical file contents.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut paths: Vec<&String> = self.entries.keys().collect();
+        paths.sort();
+
+        let mut data: Vec<u8> = vec![];
+        data.extend_from_slice(CACHE_MAGIC);
+        put_u32(&mut data, CACHE_VERSION);
+        put_u32(&mut data, paths.len() as u32);
+        for path in paths {
+            let entry = &self.entries[path];
+            put_bytes(&mut data, entry.path.as_bytes());
+            put_u64(&mut data, entry.content_hash);
+            put_u64(&mut data, entry.param_hash);
+            put_u32(&mut data, entry.width as u32);
+            put_u32(&mut data, entry.height as u32);
+            put_u32(&mut data, entry.colors.len() as u32);
+            for (color, index) in &entry.colors {
+                data.push(color[0]);
+                data.push(color[1]);
+                data.push(color[2]);
+                data.push(*index);
+            }
+            put_u64(&mut data, entry.palette_hash);
+            data.push(entry.transparent as u8);
+            put_bytes(&mut data, &entry.binary);
+        }
+        data
+    }
+}
+
+// Compute a 64-bit FNV-1a hash of some data.
+pub fn hash_bytes(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn put_u32(data: &mut Vec<u8>, value: u32) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(data: &mut Vec<u8>, value: u64) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_bytes(data: &mut Vec<u8>, bytes: &[u8]) {
+    put_u32(data, bytes.len() as u32);
+    data.extend_from_slice(bytes);
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Option<&'a [u8]> {
+        if self.position + count > self.data.len() {
+            return None;
+        }
+        let bytes = &self.data[self.position..self.position + count];
+        self.position += count;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Option<&'a [u8]> {
+        let count = self.u32()? as usize;
+        self.take(count)
+    }
+}
+
+fn decode_entries(data: &[u8]) -> Option<Vec<CacheEntry>> {
+    let mut reader = Reader { data, position: 0 };
+    if reader.take(4)? != CACHE_MAGIC || reader.u32()? != CACHE_VERSION {
+        return None;
+    }
+
+    let mut entries: Vec<CacheEntry> = vec![];
+    let count = reader.u32()?;
+    for _entry in 0..count {
+        let path = String::from_utf8(reader.bytes()?.to_vec()).ok()?;
+        let content_hash = reader.u64()?;
+        let param_hash = reader.u64()?;
+        let width = reader.u32()? as usize;
+        let height = reader.u32()? as usize;
+        let mut colors: Vec<(Rgb<u8>, u8)> = vec![];
+        for _color in 0..reader.u32()? {
+            let r = reader.u8()?;
+            let g = reader.u8()?;
+            let b = reader.u8()?;
+            let index = reader.u8()?;
+            colors.push((Rgb::<u8>([r, g, b]), index));
+        }
+        let palette_hash = reader.u64()?;
+        let transparent = reader.u8()? != 0;
+        let binary = reader.bytes()?.to_vec();
+        entries.push(CacheEntry {
+            path,
+            content_hash,
+            param_hash,
+            width,
+            height,
+            colors,
+            palette_hash,
+            transparent,
+            binary
+        });
+    }
+    Some(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str) -> CacheEntry {
+        CacheEntry {
+            path: path.to_string(),
+            content_hash: 0x0123456789abcdef,
+            param_hash: hash_bytes(path.as_bytes()),
+            width: 320,
+            height: 240,
+            colors: vec![(Rgb::<u8>([15, 8, 0]), 1), (Rgb::<u8>([0, 0, 0]), 0)],
+            palette_hash: 42,
+            transparent: true,
+            binary: vec![1, 2, 3, 255]
+        }
+    }
+
+    fn cache_with(paths: &[&str]) -> Cache {
+        let mut cache = Cache { path: String::new(), entries: HashMap::new() };
+        for path in paths {
+            cache.store(entry(path));
+        }
+        cache
+    }
+
+    #[test]
+    fn entries_survive_encoding() {
+        let cache = cache_with(&["b.png", "a.png"]);
+        let entries = decode_entries(&cache.encode()).unwrap();
+        assert_eq!(entries.len(), 2);
+        let decoded = &entries[0];
+        let original = entry("a.png");
+        assert_eq!(decoded.path, original.path);
+        assert_eq!((decoded.content_hash, decoded.param_hash), (original.content_hash, original.param_hash));
+        assert_eq!((decoded.width, decoded.height), (original.width, original.height));
+        assert_eq!(decoded.colors, original.colors);
+        assert_eq!(decoded.palette_hash, original.palette_hash);
+        assert_eq!(decoded.transparent, original.transparent);
+        assert_eq!(decoded.binary, original.binary);
+        assert_eq!(entries[1].path, "b.png");
+    }
+
+    #[test]
+    fn encoding_does_not_depend_on_insertion_order() {
+        assert_eq!(cache_with(&["a.png", "b.png", "c.png"]).encode(),
+            cache_with(&["c.png", "a.png", "b.png"]).encode());
+    }
+
+    #[test]
+    fn other_versions_and_damaged_data_are_rejected() {
+        let data = cache_with(&["a.png"]).encode();
+
+        let mut old_version = data.clone();
+        old_version[4..8].copy_from_slice(&(CACHE_VERSION - 1).to_le_bytes());
+        assert!(decode_entries(&old_version).is_none());
+
+        let mut wrong_magic = data.clone();
+        wrong_magic[0] = b'X';
+        assert!(decode_entries(&wrong_magic).is_none());
+
+        assert!(decode_entries(&data[..data.len() - 1]).is_none());
+        assert!(decode_entries(&[]).is_none());
+    }
+
+    #[test]
+    fn lookup_needs_matching_hashes() {
+        let cache = cache_with(&["a.png"]);
+        let stored = entry("a.png");
+        assert!(cache.lookup("a.png", stored.content_hash, stored.param_hash).is_some());
+        assert!(cache.lookup("a.png", stored.content_hash + 1, stored.param_hash).is_none());
+        assert!(cache.lookup("a.png", stored.content_hash, stored.param_hash + 1).is_none());
+        assert!(cache.lookup("b.png", stored.content_hash, stored.param_hash).is_none());
+    }
+
+    #[test]
+    fn hashes_are_fnv1a() {
+        assert_eq!(hash_bytes(b""), 0xcbf29ce484222325);
+        assert_eq!(hash_bytes(b"a"), 0xaf63dc4c8601ec8c);
+    }
+}
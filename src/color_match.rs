@@ -0,0 +1,235 @@
+// Nearest-color matching.
+//
+// Measures how different two 12-bit palette colors look, using one of
+// several metrics, and finds the closest color among a set of palette
+// entries. Colors are expanded to 8-bit sRGB before being measured. Ties
+// go to the lowest palette index, so that the results are deterministic.
+//
+// Copyright 2023 by Curtis Whitley
+
+use image::Rgb;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorMetric {
+    // Euclidean distance in RGB space
+    Rgb,
+    // Euclidean distance in RGB space, weighted by the mean red level
+    Redmean,
+    // CIE 1976 color difference (Euclidean distance in CIELAB space)
+    De76,
+    // CIEDE2000 color difference
+    De2000
+}
+
+impl ColorMetric {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "rgb" => Some(ColorMetric::Rgb),
+            "redmean" => Some(ColorMetric::Redmean),
+            "de76" => Some(ColorMetric::De76),
+            "de2000" => Some(ColorMetric::De2000),
+            _ => None
+        }
+    }
+}
+
+// Measure the difference between two 12-bit colors.
+pub fn distance(metric: ColorMetric, a: &Rgb<u8>, b: &Rgb<u8>) -> f64 {
+    let (r1, g1, b1) = expand(a);
+    let (r2, g2, b2) = expand(b);
+    match metric {
+        ColorMetric::Rgb => {
+            ((r1 - r2).powi(2) + (g1 - g2).powi(2) + (b1 - b2).powi(2)).sqrt()
+        },
+        ColorMetric::Redmean => {
+            let mean = (r1 + r2) / 2.0;
+            ((2.0 + mean / 256.0) * (r1 - r2).powi(2) +
+                4.0 * (g1 - g2).powi(2) +
+                (2.0 + (255.0 - mean) / 256.0) * (b1 - b2).powi(2)).sqrt()
+        },
+        ColorMetric::De76 => {
+            let (l1, a1, bb1) = to_lab(r1, g1, b1);
+            let (l2, a2, bb2) = to_lab(r2, g2, b2);
+            ((l1 - l2).powi(2) + (a1 - a2).powi(2) + (bb1 - bb2).powi(2)).sqrt()
+        },
+        ColorMetric::De2000 => {
+            ciede2000(to_lab(r1, g1, b1), to_lab(r2, g2, b2))
+        }
+    }
+}
+
+// Find the nearest of some (index, color) candidates to a color. When
+// several candidates are equally near, the lowest index wins.
+pub fn nearest<'a, I>(metric: ColorMetric, color: &Rgb<u8>, candidates: I) -> Option<(usize, f64)>
+    where I: Iterator<Item = (usize, &'a Rgb<u8>)> {
+    let mut best: Option<(usize, f64)> = None;
+    for (index, candidate) in candidates {
+        let d = distance(metric, color, candidate);
+        match best {
+            Some((best_index, best_distance)) => {
+                if d < best_distance || (d == best_distance && index < best_index) {
+                    best = Some((index, d));
+                }
+            },
+            None => {
+                best = Some((index, d));
+            }
+        }
+    }
+    best
+}
+
+// Expand 4-bit channels to 8-bit values.
+fn expand(color: &Rgb<u8>) -> (f64, f64, f64) {
+    ((color[0] * 17) as f64, (color[1] * 17) as f64, (color[2] * 17) as f64)
+}
+
+// Convert 8-bit sRGB to CIELAB (D65 white point).
+fn to_lab(r: f64, g: f64, b: f64) -> (f64, f64, f64) {
+    let linear = |c: f64| {
+        let c = c / 255.0;
+        if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    };
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+
+    let x = (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / 0.95047;
+    let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+    let z = (0.0193339 * r + 0.1191920 * g + 0.9503041 * b) / 1.08883;
+
+    let f = |t: f64| {
+        if t > 216.0 / 24389.0 { t.cbrt() } else { (24389.0 / 27.0 * t + 16.0) / 116.0 }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    (116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+// The CIEDE2000 color difference, with unit weighting factors.
+fn ciede2000(lab1: (f64, f64, f64), lab2: (f64, f64, f64)) -> f64 {
+    let (l1, a1, b1) = lab1;
+    let (l2, a2, b2) = lab2;
+
+    let c_mean = ((a1 * a1 + b1 * b1).sqrt() + (a2 * a2 + b2 * b2).sqrt()) / 2.0;
+    let c_mean7 = c_mean.powi(7);
+    let g = 0.5 * (1.0 - (c_mean7 / (c_mean7 + 25f64.powi(7))).sqrt());
+    let a1p = a1 * (1.0 + g);
+    let a2p = a2 * (1.0 + g);
+    let c1p = (a1p * a1p + b1 * b1).sqrt();
+    let c2p = (a2p * a2p + b2 * b2).sqrt();
+    let hue = |b: f64, a: f64| {
+        if b == 0.0 && a == 0.0 {
+            0.0
+        } else {
+            let h = b.atan2(a).to_degrees();
+            if h < 0.0 { h + 360.0 } else { h }
+        }
+    };
+    let h1p = hue(b1, a1p);
+    let h2p = hue(b2, a2p);
+
+    let dl = l2 - l1;
+    let dc = c2p - c1p;
+    let dh = if c1p * c2p == 0.0 {
+        0.0
+    } else if (h2p - h1p).abs() <= 180.0 {
+        h2p - h1p
+    } else if h2p - h1p > 180.0 {
+        h2p - h1p - 360.0
+    } else {
+        h2p - h1p + 360.0
+    };
+    let dhh = 2.0 * (c1p * c2p).sqrt() * (dh / 2.0).to_radians().sin();
+
+    let l_mean = (l1 + l2) / 2.0;
+    let cp_mean = (c1p + c2p) / 2.0;
+    let hp_mean = if c1p * c2p == 0.0 {
+        h1p + h2p
+    } else if (h1p - h2p).abs() <= 180.0 {
+        (h1p + h2p) / 2.0
+    } else if h1p + h2p < 360.0 {
+        (h1p + h2p + 360.0) / 2.0
+    } else {
+        (h1p + h2p - 360.0) / 2.0
+    };
+
+    let t = 1.0 - 0.17 * (hp_mean - 30.0).to_radians().cos()
+        + 0.24 * (2.0 * hp_mean).to_radians().cos()
+        + 0.32 * (3.0 * hp_mean + 6.0).to_radians().cos()
+        - 0.20 * (4.0 * hp_mean - 63.0).to_radians().cos();
+    let d_theta = 30.0 * (-((hp_mean - 275.0) / 25.0).powi(2)).exp();
+    let cp_mean7 = cp_mean.powi(7);
+    let rc = 2.0 * (cp_mean7 / (cp_mean7 + 25f64.powi(7))).sqrt();
+    let sl = 1.0 + 0.015 * (l_mean - 50.0).powi(2) / (20.0 + (l_mean - 50.0).powi(2)).sqrt();
+    let sc = 1.0 + 0.045 * cp_mean;
+    let sh = 1.0 + 0.015 * cp_mean * t;
+    let rt = -(2.0 * d_theta).to_radians().sin() * rc;
+
+    ((dl / sl).powi(2) + (dc / sc).powi(2) + (dhh / sh).powi(2) + rt * (dc / sc) * (dhh / sh)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Lab = (f64, f64, f64);
+
+    // Reference pairs from Sharma, Wu, and Dalal, "The CIEDE2000
+    // Color-Difference Formula: Implementation Notes, Supplementary Test
+    // Data, and Mathematical Observations" (2005).
+    const CIEDE2000_PAIRS: [(Lab, Lab, f64); 12] = [
+        ((50.0, 2.6772, -79.7751), (50.0, 0.0, -82.7485), 2.0425),
+        ((50.0, 3.1571, -77.2803), (50.0, 0.0, -82.7485), 2.8615),
+        ((50.0, 2.8361, -74.0200), (50.0, 0.0, -82.7485), 3.4412),
+        ((50.0, 0.0, 0.0), (50.0, -1.0, 2.0), 2.3669),
+        ((50.0, 2.4900, -0.0010), (50.0, -2.4900, 0.0009), 7.1792),
+        ((50.0, 2.4900, -0.0010), (50.0, -2.4900, 0.0010), 7.1792),
+        ((50.0, 2.4900, -0.0010), (50.0, -2.4900, 0.0011), 7.2195),
+        ((50.0, 2.4900, -0.0010), (50.0, -2.4900, 0.0012), 7.2195),
+        ((50.0, 2.5000, 0.0), (73.0, 25.0, -18.0), 27.1492),
+        ((50.0, 2.5000, 0.0), (50.0, 3.1736, 0.5854), 1.0000),
+        ((60.2574, -34.0099, 36.2677), (60.4626, -34.1751, 39.4387), 1.2644),
+        ((2.0776, 0.0795, -1.1350), (0.9033, -0.0636, -0.5514), 0.9082)
+    ];
+
+    const METRICS: [ColorMetric; 4] = [ColorMetric::Rgb, ColorMetric::Redmean, ColorMetric::De76, ColorMetric::De2000];
+
+    #[test]
+    fn ciede2000_matches_the_reference_pairs() {
+        for (lab1, lab2, expected) in CIEDE2000_PAIRS {
+            let forward = ciede2000(lab1, lab2);
+            let backward = ciede2000(lab2, lab1);
+            assert!((forward - expected).abs() < 0.00005, "{:?} {:?}: {} rather than {}", lab1, lab2, forward, expected);
+            assert!((backward - expected).abs() < 0.00005, "{:?} {:?}: {} rather than {}", lab2, lab1, backward, expected);
+        }
+    }
+
+    #[test]
+    fn white_is_lightness_100() {
+        let (l, a, b) = to_lab(255.0, 255.0, 255.0);
+        assert!((l - 100.0).abs() < 0.001 && a.abs() < 0.001 && b.abs() < 0.001, "{} {} {}", l, a, b);
+        let black = Rgb::<u8>([0, 0, 0]);
+        let white = Rgb::<u8>([15, 15, 15]);
+        assert!((distance(ColorMetric::De2000, &black, &white) - 100.0).abs() < 0.001);
+        assert!((distance(ColorMetric::De76, &black, &white) - 100.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn distances_are_zero_for_equal_colors_and_symmetric() {
+        let a = Rgb::<u8>([15, 8, 2]);
+        let b = Rgb::<u8>([3, 9, 12]);
+        for metric in METRICS {
+            assert_eq!(distance(metric, &a, &a), 0.0);
+            assert!(distance(metric, &a, &b) > 0.0);
+            assert!((distance(metric, &a, &b) - distance(metric, &b, &a)).abs() < 1e-9);
+        }
+        assert_eq!(distance(ColorMetric::Rgb, &Rgb::<u8>([0, 0, 0]), &Rgb::<u8>([0, 0, 1])), 17.0);
+    }
+
+    #[test]
+    fn ties_go_to_the_lowest_index() {
+        let candidates = [Rgb::<u8>([2, 0, 0]), Rgb::<u8>([0, 0, 0]), Rgb::<u8>([2, 0, 0])];
+        let color = Rgb::<u8>([2, 0, 0]);
+        let found = nearest(ColorMetric::Rgb, &color, candidates.iter().enumerate().rev());
+        assert_eq!(found, Some((0, 0.0)));
+        assert_eq!(nearest(ColorMetric::Rgb, &color, std::iter::empty()), None);
+    }
+}
@@ -0,0 +1,130 @@
+// Color cycling.
+//
+// A cycling range is a run of consecutive palette slots whose colors are
+// rotated, one slot per step, to animate things like water or lava. Its
+// colors are given as a list, or read from a ramp image (in pixel order,
+// left to right, then top to bottom). The slots are reserved before any
+// other colors are placed, and the palette data for each step is written
+// so that a cycling routine only needs to copy it into VERA.
+//
+// Copyright 2023 by Curtis Whitley
+
+use std::fs;
+use image::Rgb;
+use crate::{decode_image, encode_palette, parse_hex_color, pixel_color};
+
+// A cycling range, as given on the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CycleSpec {
+    pub start: usize,
+    pub colors: Vec<Rgb<u8>>,
+    pub ramp_path: Option<String>
+}
+
+impl CycleSpec {
+    // Parse "start:color,color,..." or "start:ramp.png".
+    pub fn parse(text: &str) -> Option<Self> {
+        let (start, rest) = text.split_once(':')?;
+        let start = start.parse::<usize>().ok()?;
+        if rest.to_ascii_lowercase().ends_with(".png") {
+            return Some(CycleSpec { start, colors: vec![], ramp_path: Some(rest.to_string()) });
+        }
+        let colors = rest.split(',').map(parse_hex_color).collect::<Option<Vec<Rgb<u8>>>>()?;
+        Some(CycleSpec { start, colors, ramp_path: None })
+    }
+
+    // Obtain the colors of the range, reading the ramp image if needed.
+    pub fn resolve(&self) -> Option<CycleRange> {
+        let colors = match &self.ramp_path {
+            Some(path) => read_ramp(path)?,
+            None => self.colors.clone()
+        };
+        Some(CycleRange { start: self.start, colors })
+    }
+}
+
+// A cycling range, with its colors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CycleRange {
+    pub start: usize,
+    pub colors: Vec<Rgb<u8>>
+}
+
+impl CycleRange {
+    pub fn end(&self) -> usize {
+        self.start + self.colors.len()
+    }
+
+    // Obtain the colors of the range after a number of steps. Each step
+    // moves every color up by one slot, with the last color wrapping
+    // around to the first slot.
+    pub fn step_colors(&self, step: usize) -> Vec<Option<Rgb<u8>>> {
+        let count = self.colors.len();
+        (0..count).map(|slot| Some(self.colors[(slot + count - step % count) % count])).collect()
+    }
+}
+
+// Read the unique colors of a ramp image, in pixel order.
+fn read_ramp(path: &str) -> Option<Vec<Rgb<u8>>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) => {
+            println!("ERROR: Cannot read the color cycling ramp ({}): {}", path, err);
+            return None;
+        }
+    };
+    let img = decode_image(path, &data)?;
+    let mut colors: Vec<Rgb<u8>> = vec![];
+    for pixel in img.pixels() {
+        if let Some(color) = pixel_color(pixel) {
+            if !colors.contains(&color) {
+                colors.push(color);
+            }
+        }
+    }
+    if colors.is_empty() {
+        println!("ERROR: The color cycling ramp ({}) has no colors", path);
+        return None;
+    }
+    Some(colors)
+}
+
+// Encode the steps of all ranges as one binary file. For each range, there
+// is its start index and its number of colors (which is also its number of
+// steps), followed by the palette data of the range for each step.
+pub fn encode_binary(ranges: &[CycleRange]) -> Vec<u8> {
+    let mut data: Vec<u8> = vec![
+        0, // dummy address LO
+        0  // dummy address HI
+    ];
+    for range in ranges {
+        data.push(range.start as u8);
+        data.push(range.colors.len() as u8);
+        for step in 1..=range.colors.len() {
+            data.extend_from_slice(&encode_palette(&range.step_colors(step)));
+        }
+    }
+    data
+}
+
+// Produce ca65 source for the steps of all ranges.
+pub fn ca65_text(ranges: &[CycleRange]) -> String {
+    let mut text = String::from("; Palette color cycling steps\n");
+    text.push_str(&format!("cycle_ranges = {}\n", ranges.len()));
+    for (number, range) in ranges.iter().enumerate() {
+        text.push_str(&format!("\ncycle_{}_start = {}\n", number, range.start));
+        text.push_str(&format!("cycle_{}_length = {}\n", number, range.colors.len()));
+        text.push_str(&format!("cycle_{}_vera_address = $1FA00 + {}\n", number, range.start * 2));
+        text.push_str(&format!("begin_cycle_{}_table:\n", number));
+        for step in 1..=range.colors.len() {
+            let values: Vec<String> = encode_palette(&range.step_colors(step)).iter()
+                .map(|byte| format!("${:02x}", byte)).collect();
+            for (chunk_number, chunk) in values.chunks(16).enumerate() {
+                let label = if chunk_number == 0 { format!("  ; step {}", step) } else { String::new() };
+                text.push_str(&format!("    .byte    {}{}\n", chunk.join(","), label));
+            }
+        }
+        text.push_str(&format!("end_cycle_{}_table:\n", number));
+    }
+    text
+}
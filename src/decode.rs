@@ -0,0 +1,154 @@
+// Reverse conversion.
+//
+// Decodes binary files written by this program (a 2-byte address header,
+// followed by rows of packed 1/2/4/8 bpp color indexes, or by tiles of them)
+// back into PNG files, using the colors in a palette file.
+//
+// Copyright 2023 by Curtis Whitley
+
+use std::fs;
+use image::Rgb;
+use crate::{DirParameters, Options, derived_filename, preview, tiles, write_if_changed};
+
+pub fn decode_files(directories: &[DirParameters], options: &Options) {
+    let palette_path = match &options.palette_file {
+        Some(path) => path.clone(),
+        None => "PALETTE.BIN".to_string()
+    };
+    let palette_array = match read_palette_file(&palette_path) {
+        Some(palette_array) => palette_array,
+        None => return
+    };
+
+    for directory in directories {
+        if !decode_file(directory, &palette_array) {
+            return;
+        }
+    }
+}
+
+// Read a palette file, as written by this program (with the leading 2-byte
+// address offset). Every entry is read as a color, since FREE entries cannot
+// be distinguished from black.
+pub fn read_palette_file(path: &str) -> Option<Vec<Option<Rgb<u8>>>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) => {
+            println!("ERROR: Cannot read palette file ({}): {}", path, err);
+            return None;
+        }
+    };
+    if data.len() < 2 || !(data.len() - 2).is_multiple_of(2) || data.len() > 2 + 256 * 2 {
+        println!("ERROR: Invalid palette file size ({}): {} bytes", path, data.len());
+        return None;
+    }
+
+    let mut palette_array: Vec<Option<Rgb<u8>>> = vec![None; 256];
+    for (index, entry) in data[2..].chunks(2).enumerate() {
+        // Input: [ggggbbbb] [----rrrr]
+        let r = entry[1] & 15;
+        let g = entry[0] >> 4;
+        let b = entry[0] & 15;
+        palette_array[index] = Some(Rgb::<u8>([r, g, b]));
+    }
+    println!("Read palette file ({}) as {} entries.", path, (data.len() - 2) / 2);
+    Some(palette_array)
+}
+
+fn decode_file(directory: &DirParameters, palette_array: &[Option<Rgb<u8>>]) -> bool {
+    // Validate certain options.
+    let bpp = if directory.bpp == 0 { 8 } else { directory.bpp };
+    if directory.width == 0 {
+        println!("ERROR: Please specify the width of the image to decode ({})", directory.path);
+        return false;
+    }
+    if directory.palette_offset.is_some() && bpp == 8 {
+        println!("ERROR: Do not specify palette offset with 8 bits-per-pixel");
+        return false;
+    }
+    if directory.tile_width != 0 && !directory.width.is_multiple_of(directory.tile_width) {
+        println!("ERROR: The image width must be a multiple of the tile width ({})", directory.path);
+        return false;
+    }
+
+    println!("Reading: {}", directory.path);
+    let data = match fs::read(&directory.path) {
+        Ok(data) => data,
+        Err(err) => {
+            println!("ERROR: Cannot read the specified file ({}): {}", directory.path, err);
+            return false;
+        }
+    };
+    if data.len() < 2 {
+        println!("ERROR: File is too short to be a binary file ({})", directory.path);
+        return false;
+    }
+    if data.len() == 2 {
+        println!("ERROR: File has no pixel data after its 2-byte header ({})", directory.path);
+        return false;
+    }
+
+    // Skip the dummy address bytes, and put any tiles back into rows.
+    let binary = if directory.tile_width != 0 {
+        tiles::from_tile_order(&data[2..], directory.width,
+            directory.tile_width, directory.tile_height, bpp)
+    } else {
+        data[2..].to_vec()
+    };
+
+    // Determine the height from the data size, if it is not given.
+    let row_size = (directory.width * bpp as usize).div_ceil(8);
+    let height = if directory.height == 0 {
+        binary.len().div_ceil(row_size)
+    } else {
+        directory.height
+    };
+    if binary.len() < row_size * height {
+        println!("NOTE: File {} has {} bytes, but {}x{} pixels need {} bytes; missing pixels are transparent.",
+            directory.path, binary.len(), directory.width, height, row_size * height);
+    }
+
+    let img = preview::render(&binary, directory.width, height, bpp,
+        directory.palette_offset, directory.opaque_zero, palette_array);
+    let output_path = derived_filename(&directory.path, "_DECODED", "PNG");
+    match preview::encode_png(&img) {
+        Some(png_data) => {
+            match write_if_changed(&output_path, &png_data) {
+                Ok(true) => {
+                    println!("Wrote file ({}) as {}x{} pixels.", output_path, directory.width, height);
+                },
+                Ok(false) => {
+                    println!("Kept file ({}) as {}x{} pixels (unchanged).", output_path, directory.width, height);
+                },
+                Err(err) => {
+                    println!("ERROR: Cannot write output file ({}): {}", output_path, err);
+                    return false;
+                }
+            }
+        },
+        None => {
+            println!("ERROR: Cannot encode output file ({})", output_path);
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn a_file_without_pixel_data_is_not_decoded() {
+        let path = std::env::temp_dir().join(format!("image2binary_empty_{}.bin", std::process::id()));
+        fs::write(&path, [0u8, 0u8]).unwrap();
+        let mut directory = DirParameters::current_dir();
+        directory.path = path.to_string_lossy().to_string();
+        directory.width = 8;
+        directory.height = 4;
+        assert!(!decode_file(&directory, &[None; 256]));
+        assert!(!Path::new(&derived_filename(&directory.path, "_DECODED", "PNG")).exists());
+        fs::remove_file(&path).unwrap();
+    }
+}
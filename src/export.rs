@@ -0,0 +1,204 @@
+// Palette export.
+//
+// Writes the assembled palette in formats that paint programs can load, so
+// that artists can paint with exactly the colors that the X16 will show.
+// The 4-bit color channels are expanded to 8 bits (multiplied by 17).
+// Formats that allow names or comments mark the reserved slot 0 (unless it
+// holds an opaque color), the standard slots 1-15 (or the VERA default
+// slots), and the FREE slots.
+//
+// Copyright 2023 by Curtis Whitley
+
+use image::{Rgb, Rgba, RgbaImage};
+use crate::default_palette::{PaletteMode, default_color};
+use crate::preview::encode_png;
+
+const SWATCH_SIZE: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    // GIMP palette (also read by Inkscape, Krita, and others)
+    Gpl,
+    // JASC (Paint Shop Pro) palette
+    Pal,
+    // Adobe color table
+    Act,
+    // Aseprite/Lospec hex palette
+    Hex,
+    // Swatch image
+    Png
+}
+
+impl ExportFormat {
+    // Parse a format name; "all" selects every format.
+    pub fn parse(text: &str) -> Option<Vec<Self>> {
+        match text {
+            "gpl" => Some(vec![ExportFormat::Gpl]),
+            "pal" => Some(vec![ExportFormat::Pal]),
+            "act" => Some(vec![ExportFormat::Act]),
+            "hex" => Some(vec![ExportFormat::Hex]),
+            "png" => Some(vec![ExportFormat::Png]),
+            "all" => Some(vec![ExportFormat::Gpl, ExportFormat::Pal, ExportFormat::Act,
+                ExportFormat::Hex, ExportFormat::Png]),
+            _ => None
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Gpl => "GPL",
+            ExportFormat::Pal => "PAL",
+            ExportFormat::Act => "ACT",
+            ExportFormat::Hex => "HEX",
+            ExportFormat::Png => "PNG"
+        }
+    }
+}
+
+// The role of one palette slot.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Slot {
+    Reserved,
+    Standard,
+    Default,
+    Custom,
+    Free
+}
+
+fn slot_kind(index: usize, entry: &Option<Rgb<u8>>, mode: PaletteMode) -> Slot {
+    if index == 0 && entry.is_none() {
+        Slot::Reserved
+    } else if index == 0 {
+        Slot::Custom
+    } else if entry.is_none() {
+        Slot::Free
+    } else if index < 16 && mode == PaletteMode::Standard {
+        Slot::Standard
+    } else if mode == PaletteMode::Vera && *entry == Some(default_color(index)) {
+        Slot::Default
+    } else {
+        Slot::Custom
+    }
+}
+
+// Expand a 12-bit palette color into a 24-bit color. FREE slots are black.
+fn expand(entry: &Option<Rgb<u8>>) -> [u8; 3] {
+    match entry {
+        Some(c) => [c[0] * 17, c[1] * 17, c[2] * 17],
+        None => [0, 0, 0]
+    }
+}
+
+// Produce the file data for the palette in the given format.
+pub fn export_palette(format: ExportFormat, palette_array: &[Option<Rgb<u8>>],
+                      mode: PaletteMode) -> Option<Vec<u8>> {
+    match format {
+        ExportFormat::Gpl => Some(export_gpl(palette_array, mode).into_bytes()),
+        ExportFormat::Pal => Some(export_pal(palette_array).into_bytes()),
+        ExportFormat::Act => Some(export_act(palette_array)),
+        ExportFormat::Hex => Some(export_hex(palette_array).into_bytes()),
+        ExportFormat::Png => encode_png(&export_swatches(palette_array, mode))
+    }
+}
+
+fn export_gpl(palette_array: &[Option<Rgb<u8>>], mode: PaletteMode) -> String {
+    let mut text = String::new();
+    text.push_str("GIMP Palette\n");
+    text.push_str("Name: X16 PALETTE\n");
+    text.push_str("Columns: 16\n");
+    text.push_str(match palette_array[0] {
+        Some(_) => "# Slot 0 is an opaque color",
+        None => "# Slot 0 is reserved (transparent)"
+    });
+    text.push_str(match mode {
+        PaletteMode::Standard => "; slots 1-15 are the standard colors.\n",
+        PaletteMode::Vera => "; other slots hold the VERA default colors.\n",
+        PaletteMode::None => ".\n"
+    });
+    for (index, entry) in palette_array.iter().enumerate() {
+        let rgb = expand(entry);
+        let name = match slot_kind(index, entry, mode) {
+            Slot::Reserved => format!("{:03} reserved (transparent)", index),
+            Slot::Standard => format!("{:03} standard", index),
+            Slot::Default => format!("{:03} default", index),
+            Slot::Custom => format!("{:03}", index),
+            Slot::Free => format!("{:03} FREE", index)
+        };
+        text.push_str(&format!("{:3} {:3} {:3}\t{}\n", rgb[0], rgb[1], rgb[2], name));
+    }
+    text
+}
+
+// JASC palettes have no names or comments, so slots are not marked.
+fn export_pal(palette_array: &[Option<Rgb<u8>>]) -> String {
+    let mut text = String::new();
+    text.push_str("JASC-PAL\r\n");
+    text.push_str("0100\r\n");
+    text.push_str(&format!("{}\r\n", palette_array.len()));
+    for entry in palette_array {
+        let rgb = expand(entry);
+        text.push_str(&format!("{} {} {}\r\n", rgb[0], rgb[1], rgb[2]));
+    }
+    text
+}
+
+// An ACT file holds 256 RGB triples, followed by the number of colors and
+// the index of the transparent color (big-endian), which marks slot 0, or
+// is $FFFF if slot 0 is an opaque color.
+fn export_act(palette_array: &[Option<Rgb<u8>>]) -> Vec<u8> {
+    let mut data: Vec<u8> = vec![];
+    for index in 0..256 {
+        data.extend_from_slice(&expand(palette_array.get(index).unwrap_or(&None)));
+    }
+    data.extend_from_slice(&(palette_array.len() as u16).to_be_bytes());
+    let transparent: u16 = if palette_array[0].is_some() { 0xFFFF } else { 0 };
+    data.extend_from_slice(&transparent.to_be_bytes()); // transparent index
+    data
+}
+
+// Hex palettes are one RRGGBB color per line, with nothing else.
+fn export_hex(palette_array: &[Option<Rgb<u8>>]) -> String {
+    let mut text = String::new();
+    for entry in palette_array {
+        let rgb = expand(entry);
+        text.push_str(&format!("{:02x}{:02x}{:02x}\n", rgb[0], rgb[1], rgb[2]));
+    }
+    text
+}
+
+// A 16x16 grid of color swatches. Slot 0 is transparent (unless it holds an
+// opaque color), the standard (or default) slots have a small marker in
+// their top-left corner, and the FREE slots are transparent with a gray
+// cross.
+fn export_swatches(palette_array: &[Option<Rgb<u8>>], mode: PaletteMode) -> RgbaImage {
+    let mut img = RgbaImage::new(SWATCH_SIZE * 16, SWATCH_SIZE * 16);
+    let gray = Rgba::<u8>([128, 128, 128, 255]);
+    for (index, entry) in palette_array.iter().enumerate() {
+        let left = (index as u32 % 16) * SWATCH_SIZE;
+        let top = (index as u32 / 16) * SWATCH_SIZE;
+        let rgb = expand(entry);
+        let kind = slot_kind(index, entry, mode);
+        for y in 0..SWATCH_SIZE {
+            for x in 0..SWATCH_SIZE {
+                let pixel = match kind {
+                    Slot::Reserved => continue,
+                    Slot::Free => {
+                        if x == y || x == SWATCH_SIZE - 1 - y {
+                            gray
+                        } else {
+                            continue;
+                        }
+                    },
+                    Slot::Standard | Slot::Default if x < 3 && y < 3 => {
+                        // A marker that contrasts with the color.
+                        let light = rgb[0] as u32 + rgb[1] as u32 + rgb[2] as u32 > 384;
+                        if light { Rgba::<u8>([0, 0, 0, 255]) } else { Rgba::<u8>([255, 255, 255, 255]) }
+                    },
+                    _ => Rgba::<u8>([rgb[0], rgb[1], rgb[2], 255])
+                };
+                img.put_pixel(left + x, top + y, pixel);
+            }
+        }
+    }
+    img
+}
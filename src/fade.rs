@@ -0,0 +1,161 @@
+// Palette fades.
+//
+// Produces a series of palettes that blend the assembled palette, step by
+// step, toward a target color (such as black or white), for fade-in and
+// fade-out effects. The blending is done in the 4-bit channels that VERA
+// uses, rounding to the nearest level. A transparent index 0 and FREE
+// palette entries are left alone; an opaque index 0 fades with the rest.
+//
+// Copyright 2023 by Curtis Whitley
+
+use image::Rgb;
+use crate::{encode_palette, parse_hex_color};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FadeTarget {
+    pub color: Rgb<u8>,
+    pub name: &'static str
+}
+
+impl FadeTarget {
+    pub fn black() -> Self {
+        FadeTarget { color: Rgb::<u8>([0, 0, 0]), name: "black" }
+    }
+
+    // Parse "black", "white", or a color as "rgb" or "rrggbb" hex digits.
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "black" => return Some(FadeTarget::black()),
+            "white" => return Some(FadeTarget { color: Rgb::<u8>([15, 15, 15]), name: "white" }),
+            _ => {}
+        }
+        parse_hex_color(text).map(|color| FadeTarget { color, name: "color" })
+    }
+}
+
+// Produce the faded palettes. Step k of n blends k/n of the way toward the
+// target, so the last step is entirely the target color.
+pub fn fade_palettes(palette_array: &[Option<Rgb<u8>>], target: &FadeTarget, steps: usize) -> Vec<Vec<Option<Rgb<u8>>>> {
+    let mut palettes: Vec<Vec<Option<Rgb<u8>>>> = vec![];
+    for step in 1..=steps {
+        let palette: Vec<Option<Rgb<u8>>> = palette_array.iter().map(|entry| {
+            match entry {
+                Some(color) => {
+                    let mut faded = Rgb::<u8>([0, 0, 0]);
+                    for channel in 0..3 {
+                        let from = color[channel] as usize;
+                        let to = target.color[channel] as usize;
+                        // Round to nearest: (from*(n-k) + to*k + n/2) / n
+                        faded[channel] = ((from * (steps - step) + to * step + steps / 2) / steps) as u8;
+                    }
+                    Some(faded)
+                },
+                _ => *entry
+            }
+        }).collect();
+        palettes.push(palette);
+    }
+    palettes
+}
+
+// Encode the faded palettes, one after another, as one binary file.
+pub fn encode_binary(palettes: &[Vec<Option<Rgb<u8>>>]) -> Vec<u8> {
+    let mut data: Vec<u8> = vec![
+        0, // dummy address LO
+        0  // dummy address HI
+    ];
+    for palette in palettes {
+        data.extend_from_slice(&encode_palette(palette));
+    }
+    data
+}
+
+// Produce ca65 source for the faded palettes.
+pub fn ca65_text(palettes: &[Vec<Option<Rgb<u8>>>], target: &FadeTarget, prefix: &str) -> String {
+    let mut text = format!("; Palette fade toward {} ({:x} {:x} {:x}), {} steps of 256 entries\n",
+        target.name, target.color[0], target.color[1], target.color[2], palettes.len());
+    text.push_str(&format!("begin_{}fade_table:\n", prefix));
+    for (step, palette) in palettes.iter().enumerate() {
+        text.push_str(&format!("{}fade_step_{}:\n", prefix, step + 1));
+        for (row, bytes) in encode_palette(palette).chunks(16).enumerate() {
+            let values: Vec<String> = bytes.iter().map(|byte| format!("${:02x}", byte)).collect();
+            text.push_str(&format!("    .byte    {}  ; {:03}\n", values.join(","), row * 8));
+        }
+    }
+    text.push_str(&format!("end_{}fade_table:\n", prefix));
+    text
+}
+
+// Produce C source for the faded palettes.
+pub fn c_text(palettes: &[Vec<Option<Rgb<u8>>>], target: &FadeTarget, prefix: &str) -> String {
+    let mut text = format!("// Palette fade toward {} ({:x} {:x} {:x}), {} steps of 256 entries\n",
+        target.name, target.color[0], target.color[1], target.color[2], palettes.len());
+    text.push_str(&format!("const unsigned char {}fade_table[{}][512] = {{\n", prefix, palettes.len()));
+    for (step, palette) in palettes.iter().enumerate() {
+        text.push_str(&format!("    {{ // step {}\n", step + 1));
+        for bytes in encode_palette(palette).chunks(16) {
+            let values: Vec<String> = bytes.iter().map(|byte| format!("0x{:02x}", byte)).collect();
+            text.push_str(&format!("        {},\n", values.join(",")));
+        }
+        text.push_str("    },\n");
+    }
+    text.push_str("};\n");
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn palette() -> Vec<Option<Rgb<u8>>> {
+        vec![None, Some(Rgb::<u8>([15, 8, 1])), Some(Rgb::<u8>([0, 0, 0])), None]
+    }
+
+    #[test]
+    fn first_step_has_already_moved() {
+        let palettes = fade_palettes(&palette(), &FadeTarget::black(), 2);
+        assert_eq!(palettes.len(), 2);
+        // Halfway, 15 rounds up to 8, 8 stays 4, and 1 rounds up to 1.
+        assert_eq!(palettes[0][1], Some(Rgb::<u8>([8, 4, 1])));
+    }
+
+    #[test]
+    fn last_step_is_the_target() {
+        let white = FadeTarget::parse("white").unwrap();
+        for steps in [1, 2, 7, 16, 255] {
+            let palettes = fade_palettes(&palette(), &white, steps);
+            assert_eq!(palettes.len(), steps);
+            let last = palettes.last().unwrap();
+            assert_eq!(last[1], Some(white.color));
+            assert_eq!(last[2], Some(white.color));
+        }
+    }
+
+    #[test]
+    fn levels_round_to_nearest() {
+        let palette = vec![None, Some(Rgb::<u8>([1, 2, 3]))];
+        let levels: Vec<Rgb<u8>> = fade_palettes(&palette, &FadeTarget::black(), 4)
+            .iter().map(|step| step[1].unwrap()).collect();
+        assert_eq!(levels, vec![
+            Rgb::<u8>([1, 2, 2]),
+            Rgb::<u8>([1, 1, 2]),
+            Rgb::<u8>([0, 1, 1]),
+            Rgb::<u8>([0, 0, 0])
+        ]);
+    }
+
+    #[test]
+    fn free_entries_are_left_alone() {
+        for step in fade_palettes(&palette(), &FadeTarget::black(), 3) {
+            assert_eq!(step[0], None);
+            assert_eq!(step[3], None);
+        }
+    }
+
+    #[test]
+    fn targets_are_named_or_hex_colors() {
+        assert_eq!(FadeTarget::parse("black"), Some(FadeTarget::black()));
+        assert_eq!(FadeTarget::parse("f80").map(|target| target.color), Some(Rgb::<u8>([15, 8, 0])));
+        assert_eq!(FadeTarget::parse("grey"), None);
+    }
+}
@@ -0,0 +1,117 @@
+// Text mode fonts (character sets).
+//
+// A font image is a 16x16 grid of 8x8 glyphs (128x128 pixels), which is
+// converted into a 1 bpp character set of 256 glyphs (2048 bytes), in the
+// order of VERA screen codes. Transparent and black pixels are background
+// (0 bits); any other color is foreground (1 bits). The actual colors are
+// chosen by the tile map, so fonts do not add colors to the palette.
+//
+// Copyright 2023 by Curtis Whitley
+
+pub const FONT_SIZE: usize = 2048;
+pub const FONT_GRID_SIZE: usize = 128;
+pub const DEFAULT_FONT_ADDRESS: usize = 0x1F000;
+
+const GLYPH_SIZE: usize = 8;
+
+// The order in which glyphs are drawn in the font image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GlyphOrder {
+    // Glyphs are in screen code order; nothing is rearranged.
+    Screen,
+    // Glyphs are in PETSCII character code order; the screen codes
+    // $80-$FF are made as reversed copies of the glyphs for $00-$7F.
+    Petscii,
+    // Glyphs are in ISO-8859-15 character code order, which is also the
+    // screen code order in the X16 ISO mode; nothing is rearranged.
+    Iso
+}
+
+impl GlyphOrder {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "screen" => Some(GlyphOrder::Screen),
+            "petscii" => Some(GlyphOrder::Petscii),
+            "iso" => Some(GlyphOrder::Iso),
+            _ => None
+        }
+    }
+}
+
+// Obtain the PETSCII character code that displays a given screen code
+// (for screen codes $00-$7F).
+fn petscii_from_screen_code(screen_code: usize) -> usize {
+    match screen_code {
+        0x00..=0x1f => screen_code + 0x40,
+        0x20..=0x3f => screen_code,
+        0x40..=0x5f => screen_code + 0x20,
+        _ => screen_code + 0x40
+    }
+}
+
+// Rearrange the glyphs of a character set (8 bytes per glyph, in the
+// order of the font image) into screen code order.
+pub fn remap_glyphs(charset: &[u8], order: GlyphOrder) -> Vec<u8> {
+    match order {
+        GlyphOrder::Screen | GlyphOrder::Iso => charset.to_vec(),
+        GlyphOrder::Petscii => {
+            let mut output: Vec<u8> = Vec::with_capacity(charset.len());
+            for screen_code in 0..256 {
+                let code = petscii_from_screen_code(screen_code & 0x7f);
+                let glyph = &charset[code * GLYPH_SIZE..(code + 1) * GLYPH_SIZE];
+                if screen_code < 0x80 {
+                    output.extend_from_slice(glyph);
+                } else {
+                    output.extend(glyph.iter().map(|row| !row));
+                }
+            }
+            output
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A character set where every byte of each glyph holds its position.
+    fn numbered_charset() -> Vec<u8> {
+        (0..FONT_SIZE).map(|offset| (offset / GLYPH_SIZE) as u8).collect()
+    }
+
+    fn glyph(charset: &[u8], code: usize) -> &[u8] {
+        &charset[code * GLYPH_SIZE..(code + 1) * GLYPH_SIZE]
+    }
+
+    #[test]
+    fn screen_and_iso_orders_are_unchanged() {
+        let charset = numbered_charset();
+        assert_eq!(remap_glyphs(&charset, GlyphOrder::Screen), charset);
+        assert_eq!(remap_glyphs(&charset, GlyphOrder::Iso), charset);
+    }
+
+    #[test]
+    fn petscii_glyphs_move_to_their_screen_codes() {
+        let charset = numbered_charset();
+        let remapped = remap_glyphs(&charset, GlyphOrder::Petscii);
+        assert_eq!(remapped.len(), FONT_SIZE);
+        // '@' is PETSCII $40 and screen code $00; ' ' is $20 in both.
+        assert_eq!(glyph(&remapped, 0x00), glyph(&charset, 0x40));
+        assert_eq!(glyph(&remapped, 0x20), glyph(&charset, 0x20));
+        // Screen codes $40-$5F come from PETSCII $60-$7F, and $60-$7F
+        // from PETSCII $A0-$BF.
+        assert_eq!(glyph(&remapped, 0x40), glyph(&charset, 0x60));
+        assert_eq!(glyph(&remapped, 0x7f), glyph(&charset, 0xbf));
+    }
+
+    #[test]
+    fn petscii_upper_screen_codes_are_reversed() {
+        let charset = numbered_charset();
+        let remapped = remap_glyphs(&charset, GlyphOrder::Petscii);
+        for screen_code in 0x80..0x100 {
+            let normal = glyph(&remapped, screen_code - 0x80);
+            let reversed: Vec<u8> = normal.iter().map(|row| !row).collect();
+            assert_eq!(glyph(&remapped, screen_code), &reversed[..]);
+        }
+    }
+}
@@ -0,0 +1,346 @@
+// VERA layer register setup.
+//
+// Once the VRAM layout is known, lists the register values that show each
+// bitmap, and each tile map with its tile set, on its layer, so that they
+// need not be worked out by hand. The values are given as 6502 assembly, as
+// C (using POKE from cc65's peekpoke.h), and as BASIC POKE statements. The
+// display scale registers are only visible with DCSEL = 0 (in CTRL, $9F25),
+// which is the default.
+//
+// Copyright 2023 by Curtis Whitley
+
+use crate::{FileParameters, Placement};
+use crate::asset::{AssetKind, MAP_SIZES};
+
+const DC_VIDEO: usize = 0x9F29;
+const DC_HSCALE: usize = 0x9F2A;
+const DC_VSCALE: usize = 0x9F2B;
+
+// The CONFIG register of each layer; the others follow it.
+const LAYER_CONFIG: [usize; 2] = [0x9F2D, 0x9F34];
+const MAPBASE_OFFSET: usize = 1;
+const TILEBASE_OFFSET: usize = 2;
+const HSCROLL_H_OFFSET: usize = 4;
+
+const CONFIG_BITMAP_MODE: u8 = 0x04;
+
+// One register write. A merged value is ORed into the current value, so
+// that the other bits of the register are kept.
+struct RegisterWrite {
+    address: usize,
+    value: u8,
+    merge: bool,
+    comment: String
+}
+
+// The color depth field of a layer CONFIG register.
+fn color_depth(bpp: u8) -> u8 {
+    match bpp {
+        1 => 0,
+        2 => 1,
+        4 => 2,
+        _ => 3
+    }
+}
+
+// The register writes that show a bitmap, given its VRAM address, which
+// must be on a tile base boundary.
+fn bitmap_registers(bitmap: &FileParameters, start: usize) -> Option<Vec<RegisterWrite>> {
+    if !start.is_multiple_of(AssetKind::Bitmap.alignment()) {
+        println!("ERROR: Bitmap {} is at ${:05x}, which is not a multiple of {} bytes, so it cannot be shown",
+            bitmap.path, start, AssetKind::Bitmap.alignment());
+        return None;
+    }
+    let layer = bitmap.layer.unwrap_or(0) as usize;
+    let config = LAYER_CONFIG[layer];
+    let wide = bitmap.width == 640;
+    // 320 pixels (240 lines) are doubled to fill the screen.
+    let scale: u8 = if wide { 128 } else { 64 };
+    Some(vec![
+        RegisterWrite {
+            address: config,
+            value: CONFIG_BITMAP_MODE | color_depth(bitmap.bpp),
+            merge: false,
+            comment: format!("L{}_CONFIG: bitmap mode, {} bpp", layer, bitmap.bpp)
+        },
+        RegisterWrite {
+            address: config + TILEBASE_OFFSET,
+            value: (((start >> 11) << 2) as u8) | wide as u8,
+            merge: false,
+            comment: format!("L{}_TILEBASE: address ${:05x} (bits 16:11), {} pixels wide", layer, start, bitmap.width)
+        },
+        RegisterWrite {
+            address: config + HSCROLL_H_OFFSET,
+            value: bitmap.palette_offset.unwrap_or(0) as u8,
+            merge: false,
+            comment: format!("L{}_HSCROLL_H: palette offset {}", layer, bitmap.palette_offset.unwrap_or(0))
+        },
+        RegisterWrite {
+            address: DC_HSCALE,
+            value: scale,
+            merge: false,
+            comment: format!("DC_HSCALE: {} pixels across", bitmap.width)
+        },
+        RegisterWrite {
+            address: DC_VSCALE,
+            value: scale,
+            merge: false,
+            comment: format!("DC_VSCALE: {} lines down", if wide { 480 } else { 240 })
+        },
+        RegisterWrite {
+            address: DC_VIDEO,
+            value: 0x10 << layer,
+            merge: true,
+            comment: format!("DC_VIDEO: enable layer {}", layer)
+        }
+    ])
+}
+
+// The register writes that show a tile map with its tile set, given their
+// VRAM addresses, which must be on map base and tile base boundaries.
+fn tile_layer_registers(map: &FileParameters, map_start: usize,
+                        tileset: &FileParameters, tileset_start: usize) -> Option<Vec<RegisterWrite>> {
+    for (path, start, kind) in [(&map.path, map_start, AssetKind::Tilemap), (&tileset.path, tileset_start, AssetKind::Tileset)] {
+        if !start.is_multiple_of(kind.alignment()) {
+            println!("ERROR: File {} is at ${:05x}, which is not a multiple of {} bytes, so tile map {} cannot be shown",
+                path, start, kind.alignment(), map.path);
+            return None;
+        }
+    }
+    let layer = map.layer.unwrap_or(0) as usize;
+    let config = LAYER_CONFIG[layer];
+    let map_width_code = MAP_SIZES.iter().position(|size| *size == map.width).unwrap() as u8;
+    let map_height_code = MAP_SIZES.iter().position(|size| *size == map.height).unwrap() as u8;
+    let tile_width_bit = (tileset.tile_width == 16) as u8;
+    let tile_height_bit = (tileset.tile_height == 16) as u8;
+    Some(vec![
+        RegisterWrite {
+            address: config,
+            value: (map_height_code << 6) | (map_width_code << 4) | color_depth(tileset.bpp),
+            merge: false,
+            comment: format!("L{}_CONFIG: {}x{} tile map, {} bpp", layer, map.width, map.height, tileset.bpp)
+        },
+        RegisterWrite {
+            address: config + MAPBASE_OFFSET,
+            value: (map_start >> 9) as u8,
+            merge: false,
+            comment: format!("L{}_MAPBASE: address ${:05x} (bits 16:9)", layer, map_start)
+        },
+        RegisterWrite {
+            address: config + TILEBASE_OFFSET,
+            value: (((tileset_start >> 11) << 2) as u8) | (tile_height_bit << 1) | tile_width_bit,
+            merge: false,
+            comment: format!("L{}_TILEBASE: address ${:05x} (bits 16:11), {}x{} tiles",
+                layer, tileset_start, tileset.tile_width, tileset.tile_height)
+        },
+        RegisterWrite {
+            address: DC_VIDEO,
+            value: 0x10 << layer,
+            merge: true,
+            comment: format!("DC_VIDEO: enable layer {}", layer)
+        }
+    ])
+}
+
+// Print the register writes for each bitmap. Returns false if any bitmap
+// cannot be shown where it was placed.
+pub fn print_bitmap_registers(bitmaps: &[FileParameters], layout: &[Placement]) -> bool {
+    println!("\nBitmap Layer Registers");
+    let mut shown = true;
+    for bitmap in bitmaps {
+        let start = match layout.iter().find(|placement| placement.path == bitmap.path) {
+            Some(placement) => placement.start,
+            None => continue
+        };
+        println!("\n; {}: layer {}, {}x{}, {} bpp, at ${:05x}",
+            bitmap.path, bitmap.layer.unwrap_or(0), bitmap.width, bitmap.height, bitmap.bpp, start);
+        match bitmap_registers(bitmap, start) {
+            Some(writes) => print_writes(&writes),
+            None => shown = false
+        }
+    }
+    println!();
+    shown
+}
+
+// Print the register writes for each tile map and its tile set. Returns
+// false if any tile map cannot be shown where it was placed.
+pub fn print_tile_layer_registers(tile_layers: &[(FileParameters, FileParameters)], layout: &[Placement]) -> bool {
+    println!("\nTile Layer Registers");
+    let mut shown = true;
+    for (map, tileset) in tile_layers {
+        let map_start = layout.iter().find(|placement| placement.path == map.path).map(|placement| placement.start);
+        let tileset_start = layout.iter().find(|placement| placement.path == tileset.path).map(|placement| placement.start);
+        let (map_start, tileset_start) = match (map_start, tileset_start) {
+            (Some(map_start), Some(tileset_start)) => (map_start, tileset_start),
+            _ => continue
+        };
+        println!("\n; {}: layer {}, {}x{} tiles at ${:05x}, tile set {} at ${:05x}",
+            map.path, map.layer.unwrap_or(0), map.width, map.height, map_start, tileset.path, tileset_start);
+        match tile_layer_registers(map, map_start, tileset, tileset_start) {
+            Some(writes) => print_writes(&writes),
+            None => shown = false
+        }
+    }
+    println!();
+    shown
+}
+
+fn print_writes(writes: &[RegisterWrite]) {
+    println!("\n; Assembly:");
+    for write in writes {
+        if write.merge {
+            println!("    lda ${:04x}", write.address);
+            println!("    ora #${:02x}", write.value);
+        } else {
+            println!("    lda #${:02x}", write.value);
+        }
+        println!("    sta ${:04x}    ; {}", write.address, write.comment);
+    }
+
+    println!("\n/* C: */");
+    for write in writes {
+        if write.merge {
+            println!("    POKE(0x{:04X}, PEEK(0x{:04X}) | 0x{:02X}); /* {} */",
+                write.address, write.address, write.value, write.comment);
+        } else {
+            println!("    POKE(0x{:04X}, 0x{:02X}); /* {} */", write.address, write.value, write.comment);
+        }
+    }
+
+    println!("\nREM BASIC:");
+    for write in writes {
+        if write.merge {
+            println!("    POKE ${:04X},PEEK(${:04X}) OR ${:02X}: REM {}",
+                write.address, write.address, write.value, write.comment);
+        } else {
+            println!("    POKE ${:04X},${:02X}: REM {}", write.address, write.value, write.comment);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_file;
+
+    // The value written to a register, which must be written exactly once.
+    fn value_at(writes: &[RegisterWrite], address: usize) -> u8 {
+        let matching: Vec<&RegisterWrite> = writes.iter().filter(|write| write.address == address).collect();
+        assert_eq!(matching.len(), 1, "register ${:04x}", address);
+        matching[0].value
+    }
+
+    fn bitmap(width: usize, height: usize, bpp: u8) -> FileParameters {
+        let mut bitmap = test_file("bitmap.png", 0);
+        bitmap.width = width;
+        bitmap.height = height;
+        bitmap.bpp = bpp;
+        bitmap
+    }
+
+    #[test]
+    fn a_320_pixel_8_bpp_bitmap_on_layer_0() {
+        let writes = bitmap_registers(&bitmap(320, 240, 8), 0x00000).unwrap();
+        assert_eq!(value_at(&writes, 0x9F2D), 0x07); // L0_CONFIG
+        assert_eq!(value_at(&writes, 0x9F2F), 0x00); // L0_TILEBASE
+        assert_eq!(value_at(&writes, 0x9F31), 0x00); // L0_HSCROLL_H
+        assert_eq!(value_at(&writes, DC_HSCALE), 0x40);
+        assert_eq!(value_at(&writes, DC_VSCALE), 0x40);
+        assert_eq!(value_at(&writes, DC_VIDEO), 0x10);
+        assert!(writes.iter().all(|write| write.merge == (write.address == DC_VIDEO)));
+    }
+
+    #[test]
+    fn a_640_pixel_4_bpp_bitmap_on_layer_1() {
+        let mut wide = bitmap(640, 480, 4);
+        wide.layer = Some(1);
+        wide.palette_offset = Some(3);
+        let writes = bitmap_registers(&wide, 0x04000).unwrap();
+        assert_eq!(value_at(&writes, 0x9F34), 0x06); // L1_CONFIG
+        assert_eq!(value_at(&writes, 0x9F36), 0x21); // L1_TILEBASE: $04000 >> 11 = 8, in bits 7:2
+        assert_eq!(value_at(&writes, 0x9F38), 0x03); // L1_HSCROLL_H
+        assert_eq!(value_at(&writes, DC_HSCALE), 0x80);
+        assert_eq!(value_at(&writes, DC_VSCALE), 0x80);
+        assert_eq!(value_at(&writes, DC_VIDEO), 0x20);
+    }
+
+    #[test]
+    fn the_bitmap_address_fills_the_upper_tilebase_bits() {
+        let writes = bitmap_registers(&bitmap(320, 200, 2), 0x1F800).unwrap();
+        assert_eq!(value_at(&writes, 0x9F2D), 0x05);
+        assert_eq!(value_at(&writes, 0x9F2F), 0xFC);
+    }
+
+    #[test]
+    fn a_bitmap_must_start_on_a_tile_base_boundary() {
+        assert!(bitmap_registers(&bitmap(320, 240, 8), 0x00400).is_none());
+        assert!(bitmap_registers(&bitmap(320, 240, 8), 0x1F820).is_none());
+    }
+
+    fn tile_map(width: usize, height: usize) -> FileParameters {
+        let mut map = test_file("map.png", 0);
+        map.width = width;
+        map.height = height;
+        map
+    }
+
+    fn tile_set(tile_width: usize, tile_height: usize, bpp: u8) -> FileParameters {
+        let mut tileset = test_file("tiles.png", 0);
+        tileset.tile_width = tile_width;
+        tileset.tile_height = tile_height;
+        tileset.bpp = bpp;
+        tileset
+    }
+
+    #[test]
+    fn a_64_by_32_map_of_8_by_8_tiles_on_layer_0() {
+        let writes = tile_layer_registers(&tile_map(64, 32), 0x01000, &tile_set(8, 8, 4), 0x00800).unwrap();
+        assert_eq!(value_at(&writes, 0x9F2D), 0x12); // L0_CONFIG: width code 1, height code 0, 4 bpp
+        assert_eq!(value_at(&writes, 0x9F2E), 0x08); // L0_MAPBASE: $01000 >> 9
+        assert_eq!(value_at(&writes, 0x9F2F), 0x04); // L0_TILEBASE: $00800 >> 11 = 1, in bits 7:2
+        assert_eq!(value_at(&writes, DC_VIDEO), 0x10);
+    }
+
+    #[test]
+    fn map_size_codes() {
+        for (code, size) in MAP_SIZES.iter().enumerate() {
+            let code = code as u8;
+            let wide = tile_layer_registers(&tile_map(*size, 32), 0, &tile_set(8, 8, 1), 0).unwrap();
+            assert_eq!(value_at(&wide, 0x9F2D), code << 4);
+            let high = tile_layer_registers(&tile_map(32, *size), 0, &tile_set(8, 8, 8), 0).unwrap();
+            assert_eq!(value_at(&high, 0x9F2D), (code << 6) | 0x03);
+        }
+    }
+
+    #[test]
+    fn tile_size_bits() {
+        let tilebase = |tile_width: usize, tile_height: usize| {
+            let writes = tile_layer_registers(&tile_map(32, 32), 0, &tile_set(tile_width, tile_height, 2), 0x1F000).unwrap();
+            value_at(&writes, 0x9F2F)
+        };
+        assert_eq!(tilebase(8, 8), 0xF8);
+        assert_eq!(tilebase(16, 8), 0xF9);
+        assert_eq!(tilebase(8, 16), 0xFA);
+        assert_eq!(tilebase(16, 16), 0xFB);
+    }
+
+    #[test]
+    fn a_tile_map_on_layer_1() {
+        let mut map = tile_map(128, 64);
+        map.layer = Some(1);
+        let writes = tile_layer_registers(&map, 0x1EE00, &tile_set(16, 16, 8), 0x10000).unwrap();
+        assert_eq!(value_at(&writes, 0x9F34), 0x63); // L1_CONFIG
+        assert_eq!(value_at(&writes, 0x9F35), 0xF7); // L1_MAPBASE: $1EE00 >> 9
+        assert_eq!(value_at(&writes, 0x9F36), 0x83); // L1_TILEBASE
+        assert_eq!(value_at(&writes, DC_VIDEO), 0x20);
+    }
+
+    #[test]
+    fn a_tile_layer_must_start_on_its_boundaries() {
+        let (map, tileset) = (tile_map(32, 32), tile_set(8, 8, 4));
+        assert!(tile_layer_registers(&map, 0x00200, &tileset, 0x00800).is_some());
+        assert!(tile_layer_registers(&map, 0x00100, &tileset, 0x00800).is_none());
+        assert!(tile_layer_registers(&map, 0x00200, &tileset, 0x00200).is_none());
+    }
+}
@@ -2,19 +2,135 @@
 //
 // Copyright 2023 by Curtis Whitley
 
+mod asset;
+mod banks;
+mod cache;
+mod color_match;
+mod cycle;
+mod decode;
+mod default_palette;
+mod export;
+mod fade;
+mod font;
+mod layers;
+mod preview;
+mod scale;
+mod split;
+mod stats;
+mod tiles;
+mod variants;
+mod watch;
+
 use std::{fs, cmp::Ordering};
-use std::io::Write;
-use std::{env, vec};
+use std::env;
 use std::collections::HashMap;
-use image::{Rgb};
+use std::collections::hash_map::Entry;
+use image::{Rgb, Rgba, RgbaImage};
+use asset::AssetKind;
+use cache::{Cache, CacheEntry, hash_bytes};
+use scale::ScaleMode;
+use font::GlyphOrder;
+use export::ExportFormat;
+use default_palette::PaletteMode;
+use color_match::ColorMetric;
+use fade::FadeTarget;
+use cycle::{CycleRange, CycleSpec};
+use split::SplitRule;
 
 const IMG_R: usize = 0;
 const IMG_G: usize = 1;
 const IMG_B: usize = 2;
 const IMG_A: usize = 3;
-const VRAM_PAGE_BOUNDARY: usize = 0x10000;
 const VRAM_LIMIT: usize = 0x1F9C0;
 
+// Where the input image is placed over the output image, when their sizes differ.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Anchor {
+    Center,
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest
+}
+
+impl Anchor {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "c" | "center" => Some(Anchor::Center),
+            "n" | "north" | "top" => Some(Anchor::North),
+            "ne" | "northeast" => Some(Anchor::NorthEast),
+            "e" | "east" | "right" => Some(Anchor::East),
+            "se" | "southeast" => Some(Anchor::SouthEast),
+            "s" | "south" | "bottom" => Some(Anchor::South),
+            "sw" | "southwest" => Some(Anchor::SouthWest),
+            "w" | "west" | "left" => Some(Anchor::West),
+            "nw" | "northwest" => Some(Anchor::NorthWest),
+            _ => None
+        }
+    }
+
+    // Horizontal placement: -1 (left), 0 (center), or 1 (right).
+    pub fn horizontal(&self) -> i32 {
+        match self {
+            Anchor::NorthWest | Anchor::West | Anchor::SouthWest => -1,
+            Anchor::NorthEast | Anchor::East | Anchor::SouthEast => 1,
+            _ => 0
+        }
+    }
+
+    // Vertical placement: -1 (top), 0 (center), or 1 (bottom).
+    pub fn vertical(&self) -> i32 {
+        match self {
+            Anchor::NorthWest | Anchor::North | Anchor::NorthEast => -1,
+            Anchor::SouthWest | Anchor::South | Anchor::SouthEast => 1,
+            _ => 0
+        }
+    }
+}
+
+// A color that is treated as transparent, for images without alpha.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColorKey {
+    Color(Rgb<u8>),
+    TopLeft
+}
+
+impl ColorKey {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "topleft" => Some(ColorKey::TopLeft),
+            _ => parse_hex_color(text).map(ColorKey::Color)
+        }
+    }
+}
+
+// A rectangular area within an image, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize
+}
+
+impl Rect {
+    // Parse a rectangle, given as "x,y,width,height".
+    pub fn parse(text: &str) -> Option<Self> {
+        let numbers = text.split(',')
+            .map(|part| part.trim().parse::<usize>())
+            .collect::<Result<Vec<usize>, _>>().ok()?;
+        if numbers.len() == 4 && numbers[2] > 0 && numbers[3] > 0 {
+            Some(Rect { x: numbers[0], y: numbers[1], width: numbers[2], height: numbers[3] })
+        } else {
+            None
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct DirParameters {
     pub width: usize,
@@ -24,6 +140,27 @@ struct DirParameters {
     pub palette_offset: Option<usize>,
     pub no_output: bool,
     pub vapor: bool,
+    pub decode: bool,
+    pub tile_width: usize,
+    pub tile_height: usize,
+    pub anchor: Anchor,
+    pub crop: Option<Rect>,
+    pub crop_colors: bool,
+    pub scale: Option<ScaleMode>,
+    pub font: bool,
+    pub glyph_order: GlyphOrder,
+    pub default_font: bool,
+    pub palette_set: Option<String>,
+    pub variant_of: Option<String>,
+    pub key: Option<ColorKey>,
+    pub alpha_threshold: Option<u8>,
+    pub matte: Option<Rgb<u8>>,
+    pub opaque_zero: bool,
+    pub kind: Option<AssetKind>,
+    pub pad: bool,
+    pub layer: Option<u8>,
+    pub tileset: Option<String>,
+    pub ram: bool,
     pub path: String
 }
 
@@ -37,7 +174,28 @@ impl DirParameters {
             palette_offset: None,
             no_output: false,
             vapor: false,
-            path: String::new()        
+            decode: false,
+            tile_width: 0,
+            tile_height: 0,
+            anchor: Anchor::Center,
+            crop: None,
+            crop_colors: false,
+            scale: None,
+            font: false,
+            glyph_order: GlyphOrder::Screen,
+            default_font: false,
+            palette_set: None,
+            variant_of: None,
+            key: None,
+            alpha_threshold: None,
+            matte: None,
+            opaque_zero: false,
+            kind: None,
+            pad: false,
+            layer: None,
+            tileset: None,
+            ram: false,
+            path: String::new()
         }
     }
 
@@ -58,11 +216,37 @@ struct FileParameters {
     pub palette_offset: Option<usize>,
     pub no_output: bool,
     pub vapor: bool,
+    pub anchor: Anchor,
+    pub crop: Option<Rect>,
+    pub crop_colors: bool,
+    pub scale: Option<ScaleMode>,
+    pub tile_width: usize,
+    pub tile_height: usize,
+    pub font: bool,
+    pub glyph_order: GlyphOrder,
+    pub fixed_address: Option<usize>,
+    pub palette_set: Option<String>,
+    pub variant_of: Option<String>,
+    pub key: Option<ColorKey>,
+    pub alpha_threshold: Option<u8>,
+    pub matte: Option<Rgb<u8>>,
+    pub opaque_zero: bool,
+    pub kind: Option<AssetKind>,
+    pub layer: Option<u8>,
+    pub tileset: Option<String>,
+    pub ram: bool,
     pub path: String,
     pub size: usize,
     pub max_colors: usize,
     pub colors: HashMap<Rgb<u8>, u8>,
-    pub binary: Vec<u8>
+    pub binary: Vec<u8>,
+    pub param_hash: u64,
+    pub content_hash: u64,
+    pub palette_hash: u64,
+    pub source_width: usize,
+    pub source_height: usize,
+    pub transparent: bool,
+    pub image: Option<RgbaImage>
 }
 
 impl FileParameters {
@@ -75,11 +259,37 @@ impl FileParameters {
             palette_offset: params.palette_offset,
             no_output: params.no_output,
             vapor: params.vapor,
+            anchor: params.anchor,
+            crop: params.crop,
+            crop_colors: params.crop_colors,
+            scale: params.scale,
+            tile_width: params.tile_width,
+            tile_height: params.tile_height,
+            font: params.font,
+            glyph_order: params.glyph_order,
+            fixed_address: if params.default_font { Some(font::DEFAULT_FONT_ADDRESS) } else { None },
+            palette_set: params.palette_set.clone(),
+            variant_of: params.variant_of.clone(),
+            key: params.key,
+            alpha_threshold: params.alpha_threshold,
+            matte: params.matte,
+            opaque_zero: params.opaque_zero,
+            kind: params.kind,
+            layer: params.layer,
+            tileset: params.tileset.clone(),
+            ram: params.ram,
             path: params.path.clone(),
             size: 0,
             max_colors: 0,
             colors: HashMap::new(),
-            binary: vec![]
+            binary: vec![],
+            param_hash: hash_bytes(format!("{:?}", params).as_bytes()),
+            content_hash: 0,
+            palette_hash: 0,
+            source_width: 0,
+            source_height: 0,
+            transparent: false,
+            image: None
         }
     }
 }
@@ -91,7 +301,33 @@ struct Expectations {
     pub file: bool,
     pub alignment: bool,
     pub bpp: bool,
-    pub offset: bool
+    pub offset: bool,
+    pub tiles: bool,
+    pub anchor: bool,
+    pub crop: bool,
+    pub scale: bool,
+    pub glyph_order: bool,
+    pub cache: bool,
+    pub palette_file: bool,
+    pub export: bool,
+    pub palette_mode: bool,
+    pub metric: bool,
+    pub tolerance: bool,
+    pub palette_set: bool,
+    pub layout: bool,
+    pub variant_of: bool,
+    pub fade: bool,
+    pub fade_to: bool,
+    pub cycle: bool,
+    pub key: bool,
+    pub alpha: bool,
+    pub matte: bool,
+    pub kind: bool,
+    pub layer: bool,
+    pub tileset: bool,
+    pub first_bank: bool,
+    pub boundary: bool,
+    pub split: bool
 }
 
 impl Expectations {
@@ -105,17 +341,96 @@ impl Expectations {
     }
 
     pub fn anything(&self) -> bool {
-        self.width || self.height || self.alignment || self.bpp || self.offset
+        self.width || self.height || self.alignment || self.bpp || self.offset ||
+            self.tiles || self.anchor || self.crop || self.scale || self.glyph_order || self.cache || self.palette_file ||
+            self.export || self.palette_mode || self.metric || self.tolerance ||
+            self.palette_set || self.layout || self.variant_of ||
+            self.fade || self.fade_to || self.cycle || self.key || self.alpha || self.matte ||
+            self.kind || self.layer || self.tileset || self.first_bank ||
+            self.boundary || self.split
     }
 }
 
+// Options that apply to the whole run, rather than to one directory or file.
+#[derive(Debug, Default)]
+struct Options {
+    pub cache_path: Option<String>,
+    pub watch: bool,
+    pub preview: bool,
+    pub palette_file: Option<String>,
+    pub export_formats: Vec<ExportFormat>,
+    pub palette_mode: PaletteMode,
+    pub metric: Option<ColorMetric>,
+    pub tolerance: Option<f64>,
+    pub stats: bool,
+    pub scene_layout: bool,
+    pub fade_steps: usize,
+    pub fade_target: Option<FadeTarget>,
+    pub cycles: Vec<CycleSpec>,
+    pub overflow: bool,
+    pub first_bank: Option<usize>,
+    pub split: SplitRule
+}
+
+// Where one output file was placed in VRAM.
+#[derive(Debug, Clone, PartialEq)]
+struct Placement {
+    pub path: String,
+    pub start: usize,
+    pub size: usize
+}
+
+// The overall results of processing all files once.
+#[derive(Debug, Clone)]
+struct BuildResult {
+    pub palettes: Vec<(String, Vec<Option<Rgb<u8>>>)>,
+    pub layout: Vec<Placement>
+}
+
+struct Palette {
+    pub palette_map: HashMap<Rgb<u8>, Vec<u8>>,
+    pub offset_map: Vec<HashMap<Rgb<u8>, u8>>,
+    pub palette_array: Vec<Option<Rgb<u8>>>
+}
+
 fn main() {
-    println!("Image to Binary (PNG-to-VERA file convertor) V1.6");
+    println!("Image to Binary (PNG-to-VERA file convertor) V1.31");
+
+    let args: Vec<String> = env::args().collect();
+    let (directories, options) = match parse_arguments(&args) {
+        Some(arguments) => arguments,
+        None => return
+    };
+
+    // Decoding binary files back into PNG files is a separate mode.
+    if directories.iter().any(|directory| directory.decode) {
+        if directories.iter().any(|directory| !directory.decode) {
+            println!("ERROR: Do not mix decoding (-d) with converting PNG files");
+            return;
+        }
+        decode::decode_files(&directories, &options);
+        return;
+    }
+
+    // Load the results of any previous run. When watching for changes,
+    // keep results in memory between runs, even without a cache file.
+    let mut cache = options.cache_path.as_ref().map(|path| Cache::load(path));
+    if options.watch && cache.is_none() {
+        cache = Some(Cache::new(""));
+    }
+
+    if options.watch {
+        watch::watch(&directories, &options, &mut cache);
+    } else if run(&directories, &options, &mut cache).is_none() {
+        std::process::exit(1);
+    }
+}
 
+fn parse_arguments(args: &[String]) -> Option<(Vec<DirParameters>, Options)> {
     // Determine which directories to use.
     let mut directories: Vec<DirParameters> = vec![];
+    let mut options = Options::default();
 
-    let args: Vec<String> = env::args().collect();
     if args.len() == 1 {
         // No command arguments given; use current directory only.
         directories.push(DirParameters::current_dir());
@@ -125,11 +440,11 @@ fn main() {
         let mut expect = Expectations::new();
         expect.expect_file();
 
-        for a in 1..args.len() {
-            let arg = args[a].clone().to_ascii_lowercase();
-            if arg.starts_with("-") && expect.anything() {
+        for original_arg in args.iter().skip(1) {
+            let arg = original_arg.to_ascii_lowercase();
+            if arg.starts_with('-') && expect.anything() {
                 println!("ERROR: Missing parameter value");
-                return;
+                return None;
             } else if arg.eq("-w") | arg.eq("-width") {
                 expect.width = true;
             } else if arg.eq("-h") || arg.eq("-height") {
@@ -142,6 +457,80 @@ fn main() {
                 expect.offset = true;
             } else if arg.eq("-n") || arg.eq("-nooutput") {
                 params.no_output = true;
+            } else if arg.eq("-d") || arg.eq("-decode") {
+                params.decode = true;
+            } else if arg.eq("-t") || arg.eq("-tiles") {
+                expect.tiles = true;
+            } else if arg.eq("-an") || arg.eq("-anchor") {
+                expect.anchor = true;
+            } else if arg.eq("-cr") || arg.eq("-crop") {
+                expect.crop = true;
+            } else if arg.eq("-cc") || arg.eq("-cropcolors") {
+                params.crop_colors = true;
+            } else if arg.eq("-s") || arg.eq("-scale") {
+                expect.scale = true;
+            } else if arg.eq("-f") || arg.eq("-font") {
+                params.font = true;
+            } else if arg.eq("-go") || arg.eq("-glyphorder") {
+                expect.glyph_order = true;
+            } else if arg.eq("-df") || arg.eq("-defaultfont") {
+                params.default_font = true;
+            } else if arg.eq("-c") || arg.eq("-cache") {
+                expect.cache = true;
+            } else if arg.eq("--watch") || arg.eq("-watch") {
+                options.watch = true;
+            } else if arg.eq("-pv") || arg.eq("-preview") {
+                options.preview = true;
+            } else if arg.eq("-pf") || arg.eq("-palettefile") {
+                expect.palette_file = true;
+            } else if arg.eq("-e") || arg.eq("-export") {
+                expect.export = true;
+            } else if arg.eq("-pm") || arg.eq("-palettemode") {
+                expect.palette_mode = true;
+            } else if arg.eq("-m") || arg.eq("-metric") {
+                expect.metric = true;
+            } else if arg.eq("-tolerance") {
+                expect.tolerance = true;
+            } else if arg.eq("-stats") || arg.eq("-report") {
+                options.stats = true;
+            } else if arg.eq("-set") {
+                expect.palette_set = true;
+            } else if arg.eq("-variantof") {
+                expect.variant_of = true;
+            } else if arg.eq("-key") {
+                expect.key = true;
+            } else if arg.eq("-alpha") {
+                expect.alpha = true;
+            } else if arg.eq("-matte") {
+                expect.matte = true;
+            } else if arg.eq("-z") || arg.eq("-opaquezero") {
+                params.opaque_zero = true;
+            } else if arg.eq("-k") || arg.eq("-kind") {
+                expect.kind = true;
+            } else if arg.eq("-pad") {
+                params.pad = true;
+            } else if arg.eq("-layer") {
+                expect.layer = true;
+            } else if arg.eq("-tileset") {
+                expect.tileset = true;
+            } else if arg.eq("-ram") {
+                params.ram = true;
+            } else if arg.eq("-overflow") {
+                options.overflow = true;
+            } else if arg.eq("-firstbank") {
+                expect.first_bank = true;
+            } else if arg.eq("-boundary") {
+                expect.boundary = true;
+            } else if arg.eq("-split") {
+                expect.split = true;
+            } else if arg.eq("-layout") {
+                expect.layout = true;
+            } else if arg.eq("-fade") {
+                expect.fade = true;
+            } else if arg.eq("-fadeto") {
+                expect.fade_to = true;
+            } else if arg.eq("-cycle") {
+                expect.cycle = true;
             } else if expect.width {
                 match arg.parse::<usize>() {
                     Ok(number) => {
@@ -149,8 +538,8 @@ fn main() {
                         expect.expect_file();
                     },
                     Err(err) => {
-                        println!("ERROR: Invalid width: {}", err.to_string());
-                        return;
+                        println!("ERROR: Invalid width: {}", err);
+                        return None;
                     }
                 }
             } else if expect.height {
@@ -160,8 +549,8 @@ fn main() {
                         expect.expect_file();
                     },
                     Err(err) => {
-                        println!("ERROR: Invalid width: {}", err.to_string());
-                        return;
+                        println!("ERROR: Invalid width: {}", err);
+                        return None;
                     }
                 }
             } else if expect.alignment {
@@ -180,10 +569,10 @@ fn main() {
                                 params.alignment = number;
                             },
                             Err(err) => {
-                                println!("ERROR: Invalid alignment: {}", err.to_string());
-                                return;
+                                println!("ERROR: Invalid alignment: {}", err);
+                                return None;
                             }
-                        }        
+                        }
                     }
                 }
                 expect.expect_file();
@@ -192,44 +581,301 @@ fn main() {
                     Ok(number) => {
                         if number == 1 || number == 2 || number == 4 || number == 8 {
                             params.bpp = number;
-                            expect.expect_file();    
+                            expect.expect_file();
                         } else {
                             println!("ERROR: Invalid bits-per-pixel");
-                            return;
+                            return None;
                         }
                     },
                     Err(err) => {
-                        println!("ERROR: Invalid bits-per-pixel: {}", err.to_string());
-                        return;
+                        println!("ERROR: Invalid bits-per-pixel: {}", err);
+                        return None;
                     }
                 }
             } else if expect.offset {
                 match arg.parse::<usize>() {
                     Ok(number) => {
-                        if number >= 1 && number <= 15 {
+                        if (1..=15).contains(&number) {
                             params.palette_offset = Some(number);
-                            expect.expect_file();    
+                            expect.expect_file();
                         } else {
                             println!("ERROR: Invalid palette offset");
-                            return;
+                            return None;
                         }
                     },
                     Err(err) => {
-                        println!("ERROR: Invalid palette offset: {}", err.to_string());
-                        return;
+                        println!("ERROR: Invalid palette offset: {}", err);
+                        return None;
+                    }
+                }
+            } else if expect.tiles {
+                match tiles::parse_tile_size(&arg) {
+                    Some((tile_width, tile_height)) => {
+                        params.tile_width = tile_width;
+                        params.tile_height = tile_height;
+                        expect.expect_file();
+                    },
+                    None => {
+                        println!("ERROR: Invalid tile size (must be 8, 16, 8x16, or 16x8)");
+                        return None;
+                    }
+                }
+            } else if expect.anchor {
+                match Anchor::parse(&arg) {
+                    Some(anchor) => {
+                        params.anchor = anchor;
+                        expect.expect_file();
+                    },
+                    None => {
+                        println!("ERROR: Invalid anchor (must be c, n, ne, e, se, s, sw, w, or nw)");
+                        return None;
+                    }
+                }
+            } else if expect.crop {
+                match Rect::parse(&arg) {
+                    Some(rect) => {
+                        params.crop = Some(rect);
+                        expect.expect_file();
+                    },
+                    None => {
+                        println!("ERROR: Invalid crop rectangle (must be x,y,width,height)");
+                        return None;
+                    }
+                }
+            } else if expect.scale {
+                match ScaleMode::parse(&arg) {
+                    Some(mode) => {
+                        params.scale = Some(mode);
+                        expect.expect_file();
+                    },
+                    None => {
+                        println!("ERROR: Invalid scale mode (must be nearest, bilinear, lanczos, or integer)");
+                        return None;
+                    }
+                }
+            } else if expect.glyph_order {
+                match GlyphOrder::parse(&arg) {
+                    Some(order) => {
+                        params.glyph_order = order;
+                        expect.expect_file();
+                    },
+                    None => {
+                        println!("ERROR: Invalid glyph order (must be screen, petscii, or iso)");
+                        return None;
+                    }
+                }
+            } else if expect.cache {
+                options.cache_path = Some(original_arg.clone());
+                expect.cache = false;
+            } else if expect.palette_file {
+                options.palette_file = Some(original_arg.clone());
+                expect.palette_file = false;
+            } else if expect.export {
+                match ExportFormat::parse(&arg) {
+                    Some(formats) => {
+                        for format in formats {
+                            if !options.export_formats.contains(&format) {
+                                options.export_formats.push(format);
+                            }
+                        }
+                        expect.export = false;
+                    },
+                    None => {
+                        println!("ERROR: Invalid export format (must be gpl, pal, act, hex, png, or all)");
+                        return None;
+                    }
+                }
+            } else if expect.palette_mode {
+                match PaletteMode::parse(&arg) {
+                    Some(mode) => {
+                        options.palette_mode = mode;
+                        expect.palette_mode = false;
+                    },
+                    None => {
+                        println!("ERROR: Invalid palette mode (must be standard, vera, or none)");
+                        return None;
+                    }
+                }
+            } else if expect.metric {
+                match ColorMetric::parse(&arg) {
+                    Some(metric) => {
+                        options.metric = Some(metric);
+                        expect.metric = false;
+                    },
+                    None => {
+                        println!("ERROR: Invalid color metric (must be rgb, redmean, de76, or de2000)");
+                        return None;
+                    }
+                }
+            } else if expect.palette_set {
+                // The name is used in file names and assembler labels.
+                if !original_arg.is_empty() && !original_arg.starts_with(|c: char| c.is_ascii_digit()) &&
+                        original_arg.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    params.palette_set = Some(original_arg.clone());
+                    expect.expect_file();
+                } else {
+                    println!("ERROR: Invalid palette set name (must be letters, digits, and underscores, not starting with a digit)");
+                    return None;
+                }
+            } else if expect.alpha {
+                match arg.parse::<u8>() {
+                    Ok(number) if number > 0 => {
+                        params.alpha_threshold = Some(number);
+                        expect.expect_file();
+                    },
+                    _ => {
+                        println!("ERROR: Invalid alpha threshold (must be 1 to 255)");
+                        return None;
+                    }
+                }
+            } else if expect.matte {
+                match parse_hex_color(&arg) {
+                    Some(color) => {
+                        params.matte = Some(color);
+                        expect.expect_file();
+                    },
+                    None => {
+                        println!("ERROR: Invalid matte color (must be rgb or rrggbb)");
+                        return None;
+                    }
+                }
+            } else if expect.kind {
+                match AssetKind::parse(&arg) {
+                    Some(kind) => {
+                        params.kind = Some(kind);
+                        expect.expect_file();
+                    },
+                    None => {
+                        println!("ERROR: Invalid asset kind (must be tileset, tilemap, sprite, bitmap, font, or raw)");
+                        return None;
+                    }
+                }
+            } else if expect.layer {
+                match arg.parse::<u8>() {
+                    Ok(number) if number <= 1 => {
+                        params.layer = Some(number);
+                        expect.expect_file();
+                    },
+                    _ => {
+                        println!("ERROR: Invalid layer (must be 0 or 1)");
+                        return None;
+                    }
+                }
+            } else if expect.key {
+                match ColorKey::parse(&arg) {
+                    Some(key) => {
+                        params.key = Some(key);
+                        expect.expect_file();
+                    },
+                    None => {
+                        println!("ERROR: Invalid color key (must be rgb, rrggbb, or topleft)");
+                        return None;
+                    }
+                }
+            } else if expect.variant_of {
+                params.variant_of = Some(original_arg.clone());
+                expect.expect_file();
+            } else if expect.tileset {
+                params.tileset = Some(original_arg.clone());
+                expect.expect_file();
+            } else if expect.fade {
+                match arg.parse::<usize>() {
+                    Ok(number) if (1..=255).contains(&number) => {
+                        options.fade_steps = number;
+                        expect.fade = false;
+                    },
+                    _ => {
+                        println!("ERROR: Invalid number of fade steps (must be 1 to 255)");
+                        return None;
+                    }
+                }
+            } else if expect.boundary {
+                match split::parse_size(&arg) {
+                    Some(size) => {
+                        options.split.boundary = size;
+                        expect.boundary = false;
+                    },
+                    None => {
+                        println!("ERROR: Invalid split boundary (must be a number of bytes, such as 65536 or $10000)");
+                        return None;
+                    }
+                }
+            } else if expect.split {
+                match split::parse_size(&arg) {
+                    Some(size) => {
+                        options.split.chunk_size = Some(size);
+                        expect.split = false;
+                    },
+                    None => {
+                        println!("ERROR: Invalid split size (must be a number of bytes, such as 8192 or $2000)");
+                        return None;
+                    }
+                }
+            } else if expect.first_bank {
+                match arg.parse::<usize>() {
+                    Ok(number) if number < banks::BANK_COUNT => {
+                        options.first_bank = Some(number);
+                        expect.first_bank = false;
+                    },
+                    _ => {
+                        println!("ERROR: Invalid first RAM bank (must be 0 to 255)");
+                        return None;
+                    }
+                }
+            } else if expect.cycle {
+                match CycleSpec::parse(original_arg) {
+                    Some(spec) => {
+                        options.cycles.push(spec);
+                        expect.cycle = false;
+                    },
+                    None => {
+                        println!("ERROR: Invalid color cycling range (must be start:rgb,rgb,... or start:ramp.png)");
+                        return None;
+                    }
+                }
+            } else if expect.fade_to {
+                match FadeTarget::parse(&arg) {
+                    Some(target) => {
+                        options.fade_target = Some(target);
+                        expect.fade_to = false;
+                    },
+                    None => {
+                        println!("ERROR: Invalid fade target (must be black, white, rgb, or rrggbb)");
+                        return None;
+                    }
+                }
+            } else if expect.layout {
+                match arg.as_str() {
+                    "global" => options.scene_layout = false,
+                    "scene" => options.scene_layout = true,
+                    _ => {
+                        println!("ERROR: Invalid layout (must be global or scene)");
+                        return None;
+                    }
+                }
+                expect.layout = false;
+            } else if expect.tolerance {
+                match arg.parse::<f64>() {
+                    Ok(number) if number >= 0.0 => {
+                        options.tolerance = Some(number);
+                        expect.tolerance = false;
+                    },
+                    _ => {
+                        println!("ERROR: Invalid tolerance (must be a number, zero or more)");
+                        return None;
                     }
                 }
             } else {
-                params.path = arg;
+                params.path = original_arg.clone();
                 directories.push(params);
                 params = DirParameters::new();
                 expect = Expectations::new();
             }
-        }        
+        }
 
         if expect.anything() {
             println!("ERROR: Missing parameter value");
-            return;
+            return None;
         } else if expect.file {
             params.path = "./".to_string();
             directories.push(params);
@@ -237,9 +883,44 @@ fn main() {
     }
 
     // Make sure we have something to do.
-    if directories.len() == 0 {
+    if directories.is_empty() {
         println!("ERROR: No directories to process.");
-        return;
+        return None;
+    }
+
+    Some((directories, options))
+}
+
+// Process all files once: assemble the palette, write all output files,
+// and arrange the VRAM memory map.
+fn run(directories: &[DirParameters], options: &Options, cache: &mut Option<Cache>) -> Option<BuildResult> {
+    let mut directories = directories.to_vec();
+
+    if options.tolerance.is_some() && options.metric.is_none() {
+        println!("ERROR: Specify a color metric (-m) when specifying a tolerance");
+        return None;
+    }
+    if options.fade_target.is_some() && options.fade_steps == 0 {
+        println!("ERROR: Specify the number of fade steps (-fade) when specifying a fade target");
+        return None;
+    }
+
+    // Obtain the colors of the color cycling ranges, and check that the
+    // ranges fit into the palette without overlapping.
+    let mut cycles: Vec<CycleRange> = vec![];
+    for spec in &options.cycles {
+        let range = spec.resolve()?;
+        if range.start < options.palette_mode.reserved_entries().min(16) || range.end() > 256 {
+            println!("ERROR: Color cycling range {}-{} must be within palette indexes {}-255",
+                range.start, range.end() - 1, options.palette_mode.reserved_entries().min(16));
+            return None;
+        }
+        if let Some(other) = cycles.iter().find(|other| range.start < other.end() && other.start < range.end()) {
+            println!("ERROR: Color cycling range {}-{} overlaps range {}-{}",
+                range.start, range.end() - 1, other.start, other.end() - 1);
+            return None;
+        }
+        cycles.push(range);
     }
 
     // Determine the paths to all files to process.
@@ -247,21 +928,75 @@ fn main() {
 
     for directory in &mut directories {
         // Validate certain options.
+        if directory.kind == Some(AssetKind::Font) {
+            directory.font = true;
+        }
+        if directory.font {
+            // A font is a 1 bpp tile set of 8x8 glyphs, aligned as a tile base.
+            if directory.bpp != 0 && directory.bpp != 1 {
+                println!("ERROR: A font must use 1 bit-per-pixel");
+                return None;
+            }
+            if directory.palette_offset.is_some() {
+                println!("ERROR: Do not specify palette offset for a font");
+                return None;
+            }
+            directory.bpp = 1;
+            directory.tile_width = 8;
+            directory.tile_height = 8;
+            if directory.alignment == 0 {
+                directory.alignment = 2048;
+            }
+        } else if directory.glyph_order != GlyphOrder::Screen || directory.default_font {
+            println!("ERROR: Glyph order and default font options are only used with a font (-f)");
+            return None;
+        }
         if directory.bpp == 0 {
             directory.bpp = 8;
         }
+        if !asset::validate_kind(directory) {
+            return None;
+        }
         if directory.palette_offset.is_some() && directory.bpp == 8 {
             println!("ERROR: Do not specify palette offset with 8 bits-per-pixel");
-            return;
+            return None;
         }
-        if directory.palette_offset.is_none() && directory.bpp != 8 {
+        if directory.palette_offset.is_none() && directory.bpp != 8 && !directory.font {
             println!("ERROR: Please specify palette offset with 1/2/4 bits-per-pixel");
-            return;
+            return None;
+        }
+        if directory.tile_width != 0 && directory.vapor {
+            println!("ERROR: Do not specify tile size for a map base");
+            return None;
+        }
+        if directory.crop_colors && directory.crop.is_none() {
+            println!("ERROR: Please specify a crop rectangle when using only its colors");
+            return None;
+        }
+        if directory.scale.is_some() && directory.width == 0 && directory.height == 0 {
+            println!("ERROR: Please specify the width and/or height to scale to");
+            return None;
+        }
+        if directory.opaque_zero && (directory.font || directory.vapor || directory.variant_of.is_some()) {
+            println!("ERROR: Do not specify an opaque index 0 for a font, map base, or recolor variant");
+            return None;
+        }
+        if directory.ram && (directory.vapor || directory.default_font) {
+            println!("ERROR: Do not specify banked RAM for a map base or the default font");
+            return None;
+        }
+        if directory.variant_of.is_some() {
+            // A variant only supplies colors for its base image's output.
+            if directory.palette_offset.is_none() {
+                println!("ERROR: Please specify palette offset for a recolor variant");
+                return None;
+            }
+            directory.no_output = true;
         }
 
         // Skip virtual data, as there is no directory or file.
         if directory.vapor {
-            let mut params = FileParameters::new(&directory);
+            let mut params = FileParameters::new(directory);
             params.size = directory.width * directory.height * 2;
             files.push(params);
             continue;
@@ -274,39 +1009,15 @@ fn main() {
             match fs::metadata(directory.path.clone()) {
                 Ok(metadata) => {
                     if metadata.is_file() {
-                        let img = image::open(directory.path.clone()).unwrap();
-                        let mut params = FileParameters::new(&directory);
-                        if directory.width == 0 {
-                            params.width = img.width() as usize;
-                        }
-                        if directory.height == 0 {
-                            params.height = img.height() as usize;
-                        }
-
-                        let mut width = params.width;
-                        match params.bpp {
-                            1 => {
-                                width = (width + 7) / 8;
-                            },
-                            2 => {
-                                width = (width + 3) / 4;
-                            },
-                            4 => {
-                                width = (width + 1) / 2;
-                            },
-                            _ => {}
-                        }
-                        params.size = width * params.height;
-
-                        files.push(params);
+                        files.push(size_file(directory, &directory.path)?);
                     } else {
                         println!("ERROR: Specified file is not a file: {}", directory.path);
-                        return;
+                        return None;
                     }
                 },
                 Err(_) => {
                     println!("ERROR: Cannot read the specified file: {}", directory.path);
-                    return;
+                    return None;
                 }
             }
             continue;
@@ -317,66 +1028,54 @@ fn main() {
             Ok(path) => path,
             Err(_) => {
                 println!("ERROR: Cannot read the specified directory: {}", directory.path);
-                return;
+                return None;
             }
         };
-        for path in paths {
-            match path {
-                Ok(dir_entry) => {
-                    match dir_entry.file_type() {
-                        Ok(file_type) => {
-                            if file_type.is_file() {
-                                let pathname = dir_entry.path().as_os_str().to_str().unwrap().to_string();
-                                if pathname.to_ascii_lowercase().ends_with(".png") {
-                                    let img = image::open(pathname.clone()).unwrap();
-                                    let mut params = FileParameters::new(&directory);
-                                    if directory.width == 0 {
-                                        params.width = img.width() as usize;
-                                    }
-                                    if directory.height == 0 {
-                                        params.height = img.height() as usize;
-                                    }
-
-                                    let mut width = params.width;
-                                    match params.bpp {
-                                        1 => {
-                                            width = (width + 7) / 8;
-                                        },
-                                        2 => {
-                                            width = (width + 3) / 4;
-                                        },
-                                        4 => {
-                                            width = (width + 1) / 2;
-                                        },
-                                                    _ => {}
-                                    }
-                                    params.size = width * params.height;
-                                                                        
-                                    files.push(params);
-                                }
-                            }
-                        },
-                        Err(_err) => {}
+        for dir_entry in paths.flatten() {
+            if let Ok(file_type) = dir_entry.file_type() {
+                if file_type.is_file() {
+                    let pathname = match dir_entry.path().to_str() {
+                        Some(pathname) => pathname.to_string(),
+                        None => {
+                            println!("ERROR: Cannot use the file name ({}), which is not valid UTF-8",
+                                dir_entry.path().to_string_lossy());
+                            return None;
+                        }
+                    };
+                    if pathname.to_ascii_lowercase().ends_with(".png") && !is_generated_file(&pathname) {
+                        files.push(size_file(directory, &pathname)?);
                     }
-                },
-                Err(_err) => {}
+                }
             }
         }
     }
 
     // Make sure we have something to do.
-    if files.len() == 0 {
+    if files.is_empty() {
         println!("ERROR: No files to process.");
-        return;
+        return None;
+    }
+
+    // Each tile map must find the tile set that it names.
+    for map in files.iter().filter(|file| file.tileset.is_some()) {
+        let name = map.tileset.as_deref().unwrap();
+        if find_tileset(&files, name).is_none() {
+            println!("ERROR: The tile set {} of tile map {} is not among the input tile sets or fonts",
+                name, map.path);
+            return None;
+        }
     }
 
     // Read the contents of all files, and determine their unique pixel colors.
+    // Every file with too many colors is reported before giving up.
+    let mut usages: Vec<stats::ColorUsage> = vec![];
+    let mut too_many_colors = false;
     for img_file in &mut files {
         // Determine the maximum number of colors, not including transparent
         match img_file.bpp {
             0 => {
                 img_file.bpp = 8;
-                img_file.max_colors = 239;
+                img_file.max_colors = options.palette_mode.max_colors_8bpp();
             },
             1 => {
                 img_file.max_colors = 1;
@@ -388,11 +1087,16 @@ fn main() {
                 img_file.max_colors = 15;
             },
             8 => {
-                img_file.max_colors = 239;
+                img_file.max_colors = options.palette_mode.max_colors_8bpp();
             },
             _ => {}
         }
 
+        // An opaque index 0 holds one more color.
+        if img_file.opaque_zero {
+            img_file.max_colors += 1;
+        }
+
         // Use default alignment, if needed
         if img_file.alignment == 0 {
             img_file.alignment = 1;
@@ -404,536 +1108,1207 @@ fn main() {
         }
 
         // Read the file contents
-        let img = image::open(img_file.path.clone()).unwrap();
-        let width = img.width();
-        let height = img.height();
-        println!("{}, {}x{}, {:?}", img_file.path, width, height, img.color());
-    
-        match img {
-            image::DynamicImage::ImageRgb8(rgba) => {
-                for y in 0..height {
-                    for x in 0..width {
-                        let pixel = rgba.get_pixel(x, y);
-                        let r = pixel[IMG_R] >> 4;
-                        let g = pixel[IMG_G] >> 4;
-                        let b = pixel[IMG_B] >> 4;
-                        let color = Rgb::<u8>([r, g, b]);
-                        if !img_file.colors.contains_key(&color) {
-                            if img_file.colors.len() >= img_file.max_colors {
-                                println!("ERROR: File {} contains too many colors (over {})",
-                                    img_file.path, img_file.max_colors);
-                                return;
-                            }
-                            let index = (img_file.colors.len() + 1) as u8;
-                            img_file.colors.insert(color, index);
-                        }
-                    }
-                }
-            },
-            image::DynamicImage::ImageRgba8(rgba) => {
-                for y in 0..height {
-                    for x in 0..width {
-                        let pixel = rgba.get_pixel(x, y);
-                        let a = pixel[IMG_A] >> 4;
-                        if a > 0 {
-                            let r = pixel[IMG_R] >> 4;
-                            let g = pixel[IMG_G] >> 4;
-                            let b = pixel[IMG_B] >> 4;
-                            let color = Rgb::<u8>([r, g, b]);
-                            if !img_file.colors.contains_key(&color) {
-                                if img_file.colors.len() >= img_file.max_colors {
-                                    println!("ERROR: File {} contains too many colors (over {})",
-                                        img_file.path, img_file.max_colors);
-                                    return;
-                                }
-                                let index = (img_file.colors.len() + 1) as u8;
-                                img_file.colors.insert(color, index);
-                            }
+        let data = match fs::read(&img_file.path) {
+            Ok(data) => data,
+            Err(err) => {
+                println!("ERROR: Cannot read the specified file ({}): {}", img_file.path, err);
+                return None;
+            }
+        };
+        img_file.content_hash = hash_bytes(&data);
+
+        // Reuse the colors from a previous run, if the file is unchanged.
+        // The report needs pixel counts, so it always reads the images.
+        if let Some(entry) = cache.as_ref().filter(|_cache| !options.stats).and_then(|cache|
+                cache.lookup(&img_file.path, img_file.content_hash, img_file.param_hash)) {
+            println!("{}, {}x{}, cached", img_file.path, entry.width, entry.height);
+            img_file.source_width = entry.width;
+            img_file.source_height = entry.height;
+            if !check_crop(img_file) {
+                return None;
+            }
+            // The cache does not depend on the palette mode, so the colors
+            // must be checked against the maximum again.
+            if entry.colors.len() > img_file.max_colors {
+                println!("ERROR: File {} contains too many colors (over {})",
+                    img_file.path, img_file.max_colors);
+                too_many_colors = true;
+                continue;
+            }
+            for (color, index) in &entry.colors {
+                img_file.colors.insert(*color, *index);
+            }
+            img_file.transparent = entry.transparent;
+            println!("File {} has {} unique colors (maximum is {}).",
+                img_file.path, img_file.colors.len(), img_file.max_colors);
+            continue;
+        }
+
+        let img = decode_image(&img_file.path, &data)?;
+        img_file.source_width = img.width() as usize;
+        img_file.source_height = img.height() as usize;
+        if !check_crop(img_file) {
+            return None;
+        }
+        let img = prepare_image(img_file, img);
+
+        // A font does not add colors to the palette.
+        if img_file.font {
+            img_file.image = Some(img);
+            println!("File {} is a font.", img_file.path);
+            continue;
+        }
+
+        // Use the colors of the whole image, or only of the cropped area.
+        let color_rect = if img_file.crop_colors {
+            source_rect(img_file, &img)
+        } else {
+            Rect { x: 0, y: 0, width: img.width() as usize, height: img.height() as usize }
+        };
+
+        if options.stats {
+            usages.push(stats::collect_usage(&img_file.path, img_file.bpp, img_file.palette_offset,
+                img_file.max_colors, &img, &color_rect));
+        }
+
+        let mut over_limit = false;
+        'pixels: for y in color_rect.y..color_rect.y + color_rect.height {
+            for x in color_rect.x..color_rect.x + color_rect.width {
+                if let Some(color) = pixel_color(img.get_pixel(x as u32, y as u32)) {
+                    if !img_file.colors.contains_key(&color) {
+                        if img_file.colors.len() >= img_file.max_colors {
+                            println!("ERROR: File {} contains too many colors (over {})",
+                                img_file.path, img_file.max_colors);
+                            over_limit = true;
+                            break 'pixels;
                         }
+                        let index = (img_file.colors.len() + 1) as u8;
+                        img_file.colors.insert(color, index);
                     }
                 }
-            },
-            _ => {
-                println!("ERROR: Unhandled image format ({}). Must be RGB8 or RGBA8!", img_file.path);
-                return;
             }
         }
+        if over_limit {
+            too_many_colors = true;
+            continue;
+        }
+
+        img_file.transparent = has_transparency(img_file, &img);
+        if img_file.opaque_zero && !assign_index_zero(img_file) {
+            return None;
+        }
+
+        img_file.image = Some(img);
 
         println!("File {} has {} unique colors (maximum is {}).",
             img_file.path, img_file.colors.len(), img_file.max_colors);
     }
+    if too_many_colors {
+        if options.stats {
+            stats::print_report(&usages, None, options.palette_mode);
+        }
+        return None;
+    }
 
-    // Use the colors of all files, and consolidate their palettes.
-
-    let mut palette_map: HashMap<Rgb<u8>, Vec<u8>> = HashMap::new();
-    let mut offset_map: Vec<HashMap<Rgb<u8>, u8>> = vec![];
-    let mut palette_array: Vec<Option<Rgb::<u8>>> = vec![];
-
-    for _offset in 0..16 {
-        offset_map.push(HashMap::new());
+    // Verify recolor variants, and give their colors the indexes of the
+    // corresponding colors of their base images.
+    let mut variant_report: Vec<(usize, Vec<usize>)> = vec![];
+    for index in 0..files.len() {
+        let base_path = match &files[index].variant_of {
+            Some(path) => path.clone(),
+            None => continue
+        };
+        let base_index = match files.iter().position(|file| file.variant_of.is_none() &&
+                variants::normalize_path(&file.path) == variants::normalize_path(&base_path)) {
+            Some(base_index) => base_index,
+            None => {
+                println!("ERROR: The base image {} of variant {} is not among the input files",
+                    base_path, files[index].path);
+                return None;
+            }
+        };
+        if files[base_index].palette_offset.is_none() || files[base_index].opaque_zero {
+            println!("ERROR: The base image {} of variant {} must use a palette offset, and a transparent index 0",
+                files[base_index].path, files[index].path);
+            return None;
+        }
+        let base_img = read_prepared_image(&files[base_index])?;
+        let variant_img = read_prepared_image(&files[index])?;
+        let colors = variants::match_colors(&files[base_index], &base_img, &files[index], &variant_img)?;
+        files[index].colors = colors;
+
+        match variant_report.iter_mut().find(|(base, _variants)| *base == base_index) {
+            Some((_base, variants)) => variants.push(index),
+            None => variant_report.push((base_index, vec![index]))
+        }
     }
 
-    for _index in 0..256 {
-        palette_array.push(None);
+    // Each base and variant needs its own palette offset.
+    for (base_index, variant_indexes) in &variant_report {
+        let mut used: Vec<(usize, &str)> = vec![(files[*base_index].palette_offset.unwrap(), &files[*base_index].path)];
+        for index in variant_indexes {
+            let offset = files[*index].palette_offset.unwrap();
+            if let Some((_offset, other)) = used.iter().find(|(used_offset, _path)| *used_offset == offset) {
+                println!("ERROR: Variant {} uses the same palette offset ({}) as {}",
+                    files[*index].path, offset, other);
+                return None;
+            }
+            used.push((offset, &files[*index].path));
+        }
     }
 
-    // Insert the standard palette colors.
-    palette_map.insert(Rgb::<u8>([15, 15, 15]), [1].to_vec());
-    palette_map.insert(Rgb::<u8>([8, 0, 0]), [2].to_vec());
-    palette_map.insert(Rgb::<u8>([10, 15, 14]), [3].to_vec());
-    palette_map.insert(Rgb::<u8>([12, 4, 12]), [4].to_vec());
-    palette_map.insert(Rgb::<u8>([0, 12, 5]), [5].to_vec());
-    palette_map.insert(Rgb::<u8>([0, 0, 10]), [6].to_vec());
-    palette_map.insert(Rgb::<u8>([14, 14, 7]), [7].to_vec());
-    palette_map.insert(Rgb::<u8>([13, 8, 5]), [8].to_vec());
-    palette_map.insert(Rgb::<u8>([6, 4, 0]), [9].to_vec());
-    palette_map.insert(Rgb::<u8>([15, 7, 7]), [10].to_vec());
-    palette_map.insert(Rgb::<u8>([3, 3, 3]), [11].to_vec());
-    palette_map.insert(Rgb::<u8>([7, 7, 7]), [12].to_vec());
-    palette_map.insert(Rgb::<u8>([10, 15, 6]), [13].to_vec());
-    palette_map.insert(Rgb::<u8>([0, 8, 15]), [14].to_vec());
-    palette_map.insert(Rgb::<u8>([11, 11, 11]), [15].to_vec());
+    if !variant_report.is_empty() {
+        println!("\nRecolor variants (draw the base output with each palette offset):");
+        for (base_index, variant_indexes) in &variant_report {
+            println!("    offset {:2}  {} (base)", files[*base_index].palette_offset.unwrap(), files[*base_index].path);
+            for index in variant_indexes {
+                println!("    offset {:2}  {}", files[*index].palette_offset.unwrap(), files[*index].path);
+            }
+        }
+        println!();
+    }
 
-    // Consolidate shared palette offset colors.
+    // Group the files into palette sets, each with its own palette. Files
+    // that are not in any set are common to all of the sets, and use the
+    // same palette indexes in each of them.
+    let mut set_names: Vec<Option<String>> = vec![];
     for img_file in &files {
-        match img_file.palette_offset {
-            Some(offset) => {
-                for (color, index) in &img_file.colors {
-                    if !offset_map[offset].contains_key(&color) {
-                        offset_map[offset].insert(color.clone(), *index);
-                    }
-                }
-            },
-            None => {}
+        if img_file.palette_set.is_some() && !set_names.contains(&img_file.palette_set) {
+            set_names.push(img_file.palette_set.clone());
+        }
+    }
+    set_names.sort();
+    if set_names.is_empty() {
+        if options.scene_layout {
+            println!("ERROR: A scene layout needs palette sets (-set)");
+            return None;
         }
+        set_names.push(None);
     }
 
-    // Place colors from palette offsets into the overall palette map.
-    for offset in 1..16 {
-        for (color, index) in &offset_map[offset] {
-            let palette_index = (offset * 16) as u8 + index;
-            match palette_map.get_mut(&color) {
-                Some(indexes) => {
-                    if !indexes.contains(&palette_index) {
-                        indexes.push(palette_index);
-                    }
+    let mut palettes: Vec<(String, Vec<Option<Rgb<u8>>>)> = vec![];
+    for (set_number, set_name) in set_names.iter().enumerate() {
+        // The common files come first, so that their colors are placed first.
+        let mut members: Vec<usize> = (0..files.len())
+            .filter(|index| files[*index].palette_set.is_none()).collect();
+        members.extend((0..files.len()).filter(|index| files[*index].palette_set.is_some() &&
+            files[*index].palette_set == *set_name));
+        if let Some(name) = set_name {
+            println!("\nPalette set {}:\n", name);
+        }
+
+        // Use the colors of the files in the set, and consolidate their palettes.
+        let set_files: Vec<&FileParameters> = members.iter().map(|index| &files[*index]).collect();
+        let palette = assemble_palette(&set_files, &cycles, options);
+        if options.stats {
+            let set_usages: Vec<stats::ColorUsage> = usages.iter()
+                .filter(|usage| set_files.iter().any(|file| file.path == usage.path))
+                .cloned().collect();
+            stats::print_report(&set_usages, palette.as_ref(), options.palette_mode);
+        }
+        let palette = palette?;
+
+        // Dump the palette to the console, for documentation purposes.
+        println!("; Palette entries by index:");
+        println!(";             VERA      Dec Hex:  R G B");
+        println!(";");
+        println!("begin_{}palette_table:", label_prefix(set_name));
+        for (index, entry) in palette.palette_array.iter().enumerate() {
+            let color: Rgb<u8>;
+            let free = match entry {
+                Some(c) => {
+                    color = *c;
+                    ""
                 },
                 None => {
-                    let mut indexes: Vec<u8> = vec![];
-                    indexes.push(palette_index);
-                    palette_map.insert(color.clone(), indexes);
+                    color = Rgb::<u8>([0,0,0]); // black
+                    " (FREE)"
                 }
-            }
+            };
+            println!("    .byte    ${:x}{:x},$0{:x}  ; {:03} ${:02x}:  {:x} {:x} {:x}{}",
+                color[1], color[2], color[0], // G B R
+                index, index,
+                color[0], color[1], color[2], // R G B
+                free);
         }
-    }
+        println!("end_{}palette_table:\n", label_prefix(set_name));
 
-    // Copy standard and offset colors to the palette array.
-    for (color, indexes) in &palette_map {
-        for index in indexes {
-            palette_array[*index as usize] = Some(color.clone());
-        }
-    }
+        // For each PNG file, convert its pixels to palette indexes, and write to output file.
+        for index in &members {
+            let img_file = &mut files[*index];
+            if img_file.vapor {
+                continue; // skip it
+            }
 
-    // Find indexes for all non-palette-offset (i.e., 8-bpp) colors.
-    let next_index: usize = 16;
-    for img_file in &mut files {
-        match img_file.palette_offset {
-            None => {
-                for color in img_file.colors.keys() {
-                    if !palette_map.contains_key(color) {
-                        let mut found = false;
-                        for palette_index in next_index..256 {
-                            if palette_array[palette_index].is_none() {
-                                palette_array[palette_index] = Some(color.clone());
-                                let mut indexes: Vec<u8> = vec![];
-                                indexes.push(palette_index as u8);
-                                palette_map.insert(color.clone(), indexes);            
-                                found = true;
-                                break;
-                            }
-                        }
-                        if !found {
-                            println!("ERROR: Could not insert all colors into palette (please reduce colors)");
-                            return;
-                        }
-                    }        
+            // Common files are converted with the first set; every other set
+            // must give them the same palette indexes.
+            let palette_hash = file_palette_hash(img_file, &palette)?;
+            if img_file.palette_set.is_none() && set_number > 0 {
+                if palette_hash != img_file.palette_hash {
+                    println!("ERROR: Common file {} would use different palette indexes in set {}",
+                        img_file.path, set_name.as_deref().unwrap_or(""));
+                    return None;
                 }
-            },
-            _ => {}
-        }
-    }
-
-    // Dump the palette to the console, for documentation purposes.
-    println!("; Palette entries by index:");
-    println!(";             VERA      Dec Hex:  R G B");
-    println!(";");
-    println!("begin_palette_table:");
-    for index in 0..palette_array.len() {
-        let color: Rgb<u8>;        
-        let free = match palette_array[index] {
-            Some(c) => {
-                color = c.clone();
-                ""
-            },
-            None => {
-                color = Rgb::<u8>([0,0,0]); // black
-                " (FREE)"
+                continue;
+            }
+            img_file.palette_hash = palette_hash;
+            if img_file.no_output {
+                continue; // skip it
             }
-        };
-        println!("    .byte    ${:x}{:x},$0{:x}  ; {:03} ${:02x}:  {:x} {:x} {:x}{}",
-            color[1], color[2], color[0], // G B R
-            index, index,
-            color[0], color[1], color[2], // R G B
-            free);
-    }
-    println!("end_palette_table:\n");
 
-    // For each PNG file, convert its pixels to palette indexes, and write to output file.
-    for img_file in &mut files {
-        if img_file.vapor || img_file.no_output {
-            continue; // skip it
-        }
-        let img = image::open(img_file.path.clone()).unwrap();
-
-        // Get dimensions for input image.
-        let img_width = img.width() as i32;
-        let img_height = img.height() as i32;
-        let img_center_x = img_width / 2;
-        let img_center_y = img_height / 2;
-
-        // Get dimensions for output image.
-        let out_width = img_file.width as i32;
-        let out_height = img_file.height as i32;
-        let out_center_x = out_width / 2;
-        let out_center_y = out_height / 2;
-
-        // Compute necessary rectangles.
-        let out_start_x: i32;
-        let out_end_x: i32;
-        let out_start_y: i32;
-        let out_end_y: i32;
-
-        out_start_x = 0;
-        out_end_x = out_width;
-    
-        out_start_y = 0;
-        out_end_y = out_height;
-    
-        match img {
-            image::DynamicImage::ImageRgb8(rgb) => {
-                // Convert pixel colors into indexes.
-                let mut output_data: Vec<u8> = vec![];
-                output_data.push(0); // dummy address LO
-                output_data.push(0); // dummy address HI
-
-                let mask: u8 = match img_file.bpp {
-                    1 => 1,
-                    2 => 3,
-                    4 => 15,
-                    8 => 255,
-                    _ => 0
-                };
+            // Reuse the binary data from a previous run, if nothing has changed.
+            let cached = cache.as_ref().and_then(|cache|
+                cache.lookup(&img_file.path, img_file.content_hash, img_file.param_hash))
+                .filter(|entry| entry.palette_hash == img_file.palette_hash)
+                .map(|entry| entry.binary.clone());
 
-                for out_y in out_start_y..out_end_y {
-                    let mut bits_used: u8 = 0;
-                    let mut output_byte: u8 = 0;
-        
-                    let img_y = img_center_y - (out_center_y - out_y);
-                    if img_y < 0 || img_y >= img_height {
-                        for _out_x in out_start_x..out_end_x {
-                            // output transparent color index (zero)
-                            if img_file.bpp == 8 {
-                                output_data.push(0);
-                                img_file.binary.push(0);
-                            } else {
-                                output_byte <<= img_file.bpp;
-                                bits_used += img_file.bpp;
-                                if bits_used >= 8 {
-                                    output_data.push(output_byte);
-                                    img_file.binary.push(output_byte);
-                                    output_byte = 0;
-                                    bits_used = 0;
-                                }    
-                            }
-                        }
-                    } else {
-                        for out_x in out_start_x..out_end_x {
-                            let img_x = img_center_x - (out_center_x - out_x);
-                            if img_x < 0 || img_x >= img_width {
-                                // output transparent color index (zero)
-                                if img_file.bpp == 8 {
-                                    output_data.push(0);
-                                    img_file.binary.push(0);
-                                } else {
-                                    output_byte <<= img_file.bpp;
-                                    bits_used += img_file.bpp;
-                                    if bits_used >= 8 {
-                                        output_data.push(output_byte);
-                                        img_file.binary.push(output_byte);
-                                        output_byte = 0;
-                                        bits_used = 0;
-                                    }    
-                                }
-                            } else {
-                                let pixel = rgb.get_pixel(img_x as u32, img_y as u32);
-                                let r = pixel[IMG_R] >> 4;
-                                let g = pixel[IMG_G] >> 4;
-                                let b = pixel[IMG_B] >> 4;
-                                let color = Rgb::<u8>([r, g, b]);
-
-                                let index = match img_file.palette_offset {
-                                    Some(offset) => {
-                                        // 1/2/4 bpp
-                                        offset_map[offset].get(&color).unwrap().clone() & mask
-                                    }
-                                    None => {
-                                        // 8bpp
-                                        let indexes = palette_map.get(&color).unwrap();
-                                        indexes[0]
-                                    }
-                                };
-
-                                // output some color index
-                                if img_file.bpp == 8 {
-                                    output_data.push(index);
-                                    img_file.binary.push(index);
-                                } else {
-                                    output_byte = (output_byte << img_file.bpp) | index;
-                                    bits_used += img_file.bpp;
-                                    if bits_used >= 8 {
-                                        output_data.push(output_byte);
-                                        img_file.binary.push(output_byte);
-                                        output_byte = 0;
-                                        bits_used = 0;
-                                    }    
-                                }
-                            }
-                        }    
+            match cached {
+                Some(binary) => {
+                    img_file.binary = binary;
+                },
+                None => {
+                    if img_file.image.is_none() {
+                        img_file.image = Some(read_prepared_image(img_file)?);
                     }
-                    // finish the pixel row
-                    if bits_used > 0 {
-                        while bits_used < 8 {
-                            output_byte <<= img_file.bpp;
-                            bits_used += img_file.bpp;
-                        }
-                        output_data.push(output_byte);
-                        img_file.binary.push(output_byte);
+                    img_file.binary = convert_image(img_file, img_file.image.as_ref().unwrap(), &palette)?;
+                    if img_file.tile_width != 0 {
+                        img_file.binary = tiles::to_tile_order(&img_file.binary, img_file.width,
+                            img_file.tile_width, img_file.tile_height, img_file.bpp);
+                    }
+                    if img_file.font {
+                        img_file.binary = font::remap_glyphs(&img_file.binary, img_file.glyph_order);
                     }
                 }
+            }
+            img_file.image = None;
+
+            if img_file.font && img_file.binary.len() != font::FONT_SIZE {
+                println!("ERROR: Font {} has {} bytes of data, rather than {}",
+                    img_file.path, img_file.binary.len(), font::FONT_SIZE);
+                return None;
+            }
+
+            // Write the output data to a file.
+            let mut output_data: Vec<u8> = vec![];
+            output_data.push(0); // dummy address LO
+            output_data.push(0); // dummy address HI
+            output_data.extend_from_slice(&img_file.binary);
+
+            let uc_path = upcase_filename(&img_file.path);
+            match write_if_changed(&uc_path, &output_data) {
+                Ok(true) => {
+                    println!("Wrote file ({}) as {} bytes.", uc_path, output_data.len());
+                },
+                Ok(false) => {
+                    println!("Kept file ({}) as {} bytes (unchanged).", uc_path, output_data.len());
+                },
+                Err(err) => {
+                    println!("ERROR: Cannot write output file ({}): {}", uc_path, err);
+                }
+            }
 
-                // Write the output data to a file.
-                let uc_path = upcase_filename(&img_file.path);
-                match fs::File::create(uc_path.clone()) {
-                    Ok(mut file) => {
-                        match file.write_all(&output_data[..]) {
-                            Ok(()) => {
-                                println!("Wrote file ({}) as {} bytes.", uc_path, output_data.len());
+            // Show what the X16 will display, as a PNG file.
+            if options.preview {
+                let row_data = if img_file.tile_width != 0 {
+                    tiles::from_tile_order(&img_file.binary, img_file.width,
+                        img_file.tile_width, img_file.tile_height, img_file.bpp)
+                } else {
+                    img_file.binary.clone()
+                };
+                let preview_img = preview::render(&row_data, img_file.width, img_file.height,
+                    img_file.bpp, img_file.palette_offset, img_file.opaque_zero, &palette.palette_array);
+                let preview_path = derived_filename(&img_file.path, "_PREVIEW", "PNG");
+                match preview::encode_png(&preview_img) {
+                    Some(png_data) => {
+                        match write_if_changed(&preview_path, &png_data) {
+                            Ok(true) => {
+                                println!("Wrote file ({}) as {}x{} pixels.", preview_path, img_file.width, img_file.height);
+                            },
+                            Ok(false) => {
+                                println!("Kept file ({}) as {}x{} pixels (unchanged).", preview_path, img_file.width, img_file.height);
                             },
                             Err(err) => {
-                                println!("ERROR: Cannot write output file ({}): {}", uc_path, err.to_string());
+                                println!("ERROR: Cannot write preview file ({}): {}", preview_path, err);
                             }
                         }
                     },
-                    Err(err) => {
-                        println!("ERROR: Cannot open output file ({}): {}", uc_path, err.to_string());
+                    None => {
+                        println!("ERROR: Cannot encode preview file ({})", preview_path);
                     }
                 }
+            }
+        }
+
+        // Write the palette data to a file.
+        let mut palette_bytes: Vec<u8> = vec![
+            // 2-byte address offset
+            0, // dummy address LO
+            0  // dummy address HI
+        ];
+        // standard and custom colors
+        palette_bytes.extend_from_slice(&encode_palette(&palette.palette_array));
+
+        let uc_path = palette_filename(set_name, "BIN");
+        match write_if_changed(&uc_path, &palette_bytes) {
+            Ok(true) => {
+                println!("Wrote file ({}) as {} bytes.", uc_path, palette_bytes.len());
             },
-            image::DynamicImage::ImageRgba8(rgba) => {
-                // Convert pixel colors into indexes.
-                let mut output_data: Vec<u8> = vec![];
-                output_data.push(0); // dummy address LO
-                output_data.push(0); // dummy address HI
-
-                let mask: u8 = match img_file.bpp {
-                    1 => 1,
-                    2 => 3,
-                    4 => 15,
-                    8 => 255,
-                    _ => 0
-                };
+            Ok(false) => {
+                println!("Kept file ({}) as {} bytes (unchanged).", uc_path, palette_bytes.len());
+            },
+            Err(err) => {
+                println!("ERROR: Cannot write palette file ({}): {}", uc_path, err);
+            }
+        }
 
-                for out_y in out_start_y..out_end_y {
-                    let mut bits_used: u8 = 0;
-                    let mut output_byte: u8 = 0;
-        
-                    let img_y = img_center_y - (out_center_y - out_y);
-                    if img_y < 0 || img_y >= img_height {
-                        for _out_x in out_start_x..out_end_x {
-                            // output transparent color index (zero)
-                            if img_file.bpp == 8 {
-                                output_data.push(0);
-                                img_file.binary.push(0);
-                            } else {
-                                output_byte <<= img_file.bpp;
-                                bits_used += img_file.bpp;
-                                if bits_used >= 8 {
-                                    output_data.push(output_byte);
-                                    img_file.binary.push(output_byte);
-                                    output_byte = 0;
-                                    bits_used = 0;
-                                }    
-                            }
-                        }
-                    } else {
-                        for out_x in out_start_x..out_end_x {
-                            let img_x = img_center_x - (out_center_x - out_x);
-                            if img_x < 0 || img_x >= img_width {
-                                // output transparent color index (zero)
-                                if img_file.bpp == 8 {
-                                    output_data.push(0);
-                                    img_file.binary.push(0);
-                                } else {
-                                    output_byte <<= img_file.bpp;
-                                    bits_used += img_file.bpp;
-                                    if bits_used >= 8 {
-                                        output_data.push(output_byte);
-                                        img_file.binary.push(output_byte);
-                                        output_byte = 0;
-                                        bits_used = 0;
-                                    }    
-                                }
-                            } else {
-                                let pixel = rgba.get_pixel(img_x as u32, img_y as u32);
-                                let a = pixel[IMG_A] >> 4;
-                                if a > 0 {
-                                    let r = pixel[IMG_R] >> 4;
-                                    let g = pixel[IMG_G] >> 4;
-                                    let b = pixel[IMG_B] >> 4;
-                                    let color = Rgb::<u8>([r, g, b]);
-    
-                                    let index = match img_file.palette_offset {
-                                        Some(offset) => {
-                                            // 1/2/4 bpp
-                                            offset_map[offset].get(&color).unwrap().clone() & mask
-                                        }
-                                        None => {
-                                            // 8bpp
-                                            let indexes = palette_map.get(&color).unwrap();
-                                            indexes[0]
-                                        }
-                                    };
-    
-                                    // output some color index
-                                    if img_file.bpp == 8 {
-                                        output_data.push(index);
-                                        img_file.binary.push(index);
-                                    } else {
-                                        output_byte = (output_byte << img_file.bpp) | index;
-                                        bits_used += img_file.bpp;
-                                        if bits_used >= 8 {
-                                            output_data.push(output_byte);
-                                            img_file.binary.push(output_byte);
-                                            output_byte = 0;
-                                            bits_used = 0;
-                                        }    
-                                    }
-                                } else {
-                                    // output transparent color index (zero)
-                                    if img_file.bpp == 8 {
-                                        output_data.push(0);
-                                        img_file.binary.push(0);
-                                    } else {
-                                        output_byte <<= img_file.bpp;
-                                        bits_used += img_file.bpp;
-                                        if bits_used >= 8 {
-                                            output_data.push(output_byte);
-                                            img_file.binary.push(output_byte);
-                                            output_byte = 0;
-                                            bits_used = 0;
-                                        }    
-                                    }
-                                }
-                            }
-                        }    
+        // Export the palette for use in paint programs.
+        for format in &options.export_formats {
+            let export_path = palette_filename(set_name, format.extension());
+            let export_bytes = match export::export_palette(*format, &palette.palette_array, options.palette_mode) {
+                Some(bytes) => bytes,
+                None => {
+                    println!("ERROR: Cannot encode palette export ({})", export_path);
+                    continue;
+                }
+            };
+            match write_if_changed(&export_path, &export_bytes) {
+                Ok(true) => {
+                    println!("Wrote file ({}) as {} bytes.", export_path, export_bytes.len());
+                },
+                Ok(false) => {
+                    println!("Kept file ({}) as {} bytes (unchanged).", export_path, export_bytes.len());
+                },
+                Err(err) => {
+                    println!("ERROR: Cannot write palette export ({}): {}", export_path, err);
+                }
+            }
+        }
+
+        // Write the palette fade tables.
+        if options.fade_steps > 0 {
+            let target = options.fade_target.unwrap_or_else(FadeTarget::black);
+            let fades = fade::fade_palettes(&palette.palette_array, &target, options.fade_steps);
+            let prefix = label_prefix(set_name);
+            let fade_files = [
+                (fade_filename(set_name, "BIN"), fade::encode_binary(&fades)),
+                (fade_filename(set_name, "INC"), fade::ca65_text(&fades, &target, &prefix).into_bytes()),
+                (fade_filename(set_name, "H"), fade::c_text(&fades, &target, &prefix).into_bytes())
+            ];
+            for (fade_path, fade_bytes) in &fade_files {
+                match write_if_changed(fade_path, fade_bytes) {
+                    Ok(true) => {
+                        println!("Wrote file ({}) as {} bytes.", fade_path, fade_bytes.len());
+                    },
+                    Ok(false) => {
+                        println!("Kept file ({}) as {} bytes (unchanged).", fade_path, fade_bytes.len());
+                    },
+                    Err(err) => {
+                        println!("ERROR: Cannot write fade file ({}): {}", fade_path, err);
                     }
-                    // finish the pixel row
-                    if bits_used > 0 {
-                        while bits_used < 8 {
-                            output_byte <<= img_file.bpp;
-                            bits_used += img_file.bpp;
-                        }
-                        output_data.push(output_byte);
-                        img_file.binary.push(output_byte);
+                }
+            }
+        }
+
+        palettes.push((set_name.clone().unwrap_or_default(), palette.palette_array));
+    }
+
+    // Write the color cycling steps.
+    if !cycles.is_empty() {
+        let cycle_files = [
+            ("CYCLE.BIN".to_string(), cycle::encode_binary(&cycles)),
+            ("CYCLE.INC".to_string(), cycle::ca65_text(&cycles).into_bytes())
+        ];
+        for (cycle_path, cycle_bytes) in &cycle_files {
+            match write_if_changed(cycle_path, cycle_bytes) {
+                Ok(true) => {
+                    println!("Wrote file ({}) as {} bytes.", cycle_path, cycle_bytes.len());
+                },
+                Ok(false) => {
+                    println!("Kept file ({}) as {} bytes (unchanged).", cycle_path, cycle_bytes.len());
+                },
+                Err(err) => {
+                    println!("ERROR: Cannot write color cycling file ({}): {}", cycle_path, err);
+                }
+            }
+        }
+    }
+
+    // Remember the results of this run, for the next run.
+    if let Some(cache) = cache {
+        for img_file in &mut files {
+            if img_file.vapor {
+                continue; // skip it
+            }
+            cache.store(CacheEntry {
+                path: img_file.path.clone(),
+                content_hash: img_file.content_hash,
+                param_hash: img_file.param_hash,
+                width: img_file.source_width,
+                height: img_file.source_height,
+                colors: colors_in_order(&img_file.colors),
+                palette_hash: img_file.palette_hash,
+                transparent: img_file.transparent,
+                binary: img_file.binary.clone()
+            });
+        }
+
+        if !cache.path.is_empty() {
+            if let Err(err) = write_if_changed(&cache.path, &cache.encode()) {
+                println!("ERROR: Cannot write cache file ({}): {}", cache.path, err);
+            }
+        }
+    }
+
+    let sprites: Vec<FileParameters> = files.iter()
+        .filter(|file| file.kind == Some(AssetKind::Sprite) && !file.no_output)
+        .cloned().collect();
+    let bitmaps: Vec<FileParameters> = files.iter()
+        .filter(|file| file.kind == Some(AssetKind::Bitmap) && !file.no_output)
+        .cloned().collect();
+    let tile_layers: Vec<(FileParameters, FileParameters)> = files.iter()
+        .filter_map(|map| map.tileset.as_deref()
+            .and_then(|name| find_tileset(&files, name))
+            .map(|tileset| (map.clone(), tileset.clone())))
+        .collect();
+
+    // Files that are marked for banked RAM are not placed in VRAM; with
+    // '-overflow', neither are the files that do not fit into VRAM.
+    let (mut ram_files, mut files): (Vec<FileParameters>, Vec<FileParameters>) =
+        files.into_iter().partition(|file| file.ram && !file.no_output);
+    ram_files.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut overflow: Vec<FileParameters> = vec![];
+    let overflow_files = if options.overflow { Some(&mut overflow) } else { None };
+
+    let layout = if options.scene_layout {
+        arrange_scenes(files, &set_names, overflow_files, &options.split)?
+    } else {
+        arrange_files_in_memory(&mut files, overflow_files, &[], &options.split)?
+    };
+
+    for file in overflow {
+        if !ram_files.iter().any(|ram_file| ram_file.path == file.path) {
+            ram_files.push(file);
+        }
+    }
+    if !ram_files.is_empty() && !write_banks(&ram_files, options) {
+        return None;
+    }
+
+    if !sprites.is_empty() {
+        asset::print_sprite_attributes(&sprites, &layout);
+    }
+    if !bitmaps.is_empty() && !layers::print_bitmap_registers(&bitmaps, &layout) {
+        return None;
+    }
+    if !tile_layers.is_empty() && !layers::print_tile_layer_registers(&tile_layers, &layout) {
+        return None;
+    }
+
+    Some(BuildResult {
+        palettes,
+        layout
+    })
+}
+
+// Place files into banked RAM, and write the file for each bank, along
+// with the table of where each file was placed.
+fn write_banks(files: &[FileParameters], options: &Options) -> bool {
+    let first_bank = options.first_bank.unwrap_or(banks::DEFAULT_FIRST_BANK);
+    let (placements, ram_banks) = match banks::place_in_banks(files, first_bank) {
+        Some(result) => result,
+        None => return false
+    };
+    banks::print_table(&placements);
+    println!();
+
+    let mut bank_files: Vec<(String, Vec<u8>)> = ram_banks.iter().map(|bank| {
+        let mut output_data: Vec<u8> = vec![
+            0, // dummy address byte
+            0  // dummy address byte
+        ];
+        output_data.extend_from_slice(&bank.data);
+        (banks::bank_filename(bank.number), output_data)
+    }).collect();
+    bank_files.push(("BANKS.INC".to_string(), banks::ca65_text(&placements).into_bytes()));
+    for (bank_path, bank_bytes) in &bank_files {
+        match write_if_changed(bank_path, bank_bytes) {
+            Ok(true) => {
+                println!("Wrote file ({}) as {} bytes.", bank_path, bank_bytes.len());
+            },
+            Ok(false) => {
+                println!("Kept file ({}) as {} bytes (unchanged).", bank_path, bank_bytes.len());
+            },
+            Err(err) => {
+                println!("ERROR: Cannot write banked RAM file ({}): {}", bank_path, err);
+            }
+        }
+    }
+    true
+}
+
+// Find the tile set (or font) that a tile map names, by its path.
+fn find_tileset<'a>(files: &'a [FileParameters], name: &str) -> Option<&'a FileParameters> {
+    files.iter().find(|file| (file.kind == Some(AssetKind::Tileset) || file.font) &&
+        variants::normalize_path(&file.path) == variants::normalize_path(name))
+}
+
+// The name of a file in the layout report, along with its asset kind.
+fn layout_name(file: &FileParameters) -> String {
+    match file.kind {
+        Some(kind) => format!("{} ({})", file.path, kind.name()),
+        None => file.path.clone()
+    }
+}
+
+// Arrange VRAM separately for each palette set (scene). The common files
+// are arranged (and listed) once, and each scene is arranged around them.
+// Common files that overflow VRAM are left out of every scene.
+fn arrange_scenes(files: Vec<FileParameters>, set_names: &[Option<String>],
+                  mut overflow: Option<&mut Vec<FileParameters>>, split: &SplitRule) -> Option<Vec<Placement>> {
+    let (common, others): (Vec<FileParameters>, Vec<FileParameters>) =
+        files.into_iter().partition(|file| file.palette_set.is_none());
+
+    println!("\nCommon files:");
+    let mut layout = arrange_files_in_memory(&mut common.clone(), overflow.as_deref_mut(), &[], split)?;
+    let common_layout = layout.clone();
+
+    for set_name in set_names {
+        let mut scene_files: Vec<FileParameters> = others.iter()
+            .filter(|file| file.palette_set == *set_name).cloned().collect();
+
+        println!("\nScene {}:", set_name.as_deref().unwrap_or(""));
+        layout.extend(arrange_files_in_memory(&mut scene_files, overflow.as_deref_mut(), &common_layout, split)?);
+    }
+    Some(layout)
+}
+
+// Determine the size of the output data for a single PNG file.
+fn size_file(directory: &DirParameters, pathname: &str) -> Option<FileParameters> {
+    let mut params = FileParameters::new(directory);
+    params.path = pathname.to_string();
+
+    if directory.width == 0 || directory.height == 0 {
+        // The output size defaults to the size of the crop rectangle, or of
+        // the image. Only the image header is needed to obtain its dimensions.
+        let (base_width, base_height) = match directory.crop {
+            Some(crop) => (crop.width, crop.height),
+            None => {
+                match image::image_dimensions(pathname) {
+                    Ok((width, height)) => (width as usize, height as usize),
+                    Err(err) => {
+                        println!("ERROR: Cannot read the specified file ({}): {}", pathname, err);
+                        return None;
                     }
                 }
+            }
+        };
 
-                // Write the output data to a file.
-                let uc_path = upcase_filename(&img_file.path);
-                match fs::File::create(uc_path.clone()) {
-                    Ok(mut file) => {
-                        match file.write_all(&output_data[..]) {
-                            Ok(()) => {
-                                println!("Wrote file ({}) as {} bytes.", uc_path, output_data.len());
-                            },
-                            Err(err) => {
-                                println!("ERROR: Cannot write output file ({}): {}", uc_path, err.to_string());
+        if directory.scale.is_some() && directory.width != 0 {
+            // Keep the aspect ratio when scaling to a given width.
+            params.height = ((base_height * directory.width + base_width / 2) / base_width).max(1);
+        } else if directory.scale.is_some() && directory.height != 0 {
+            // Keep the aspect ratio when scaling to a given height.
+            params.width = ((base_width * directory.height + base_height / 2) / base_height).max(1);
+        } else {
+            if directory.width == 0 {
+                params.width = base_width;
+            }
+            if directory.height == 0 {
+                params.height = base_height;
+            }
+        }
+    }
+
+    // The size must suit the asset kind.
+    if !asset::check_size(&mut params, directory.pad) {
+        return None;
+    }
+
+    let width = match params.bpp {
+        1 => params.width.div_ceil(8),
+        2 => params.width.div_ceil(4),
+        4 => params.width.div_ceil(2),
+        _ => params.width
+    };
+    params.size = width * params.height;
+
+    // A font must be a 16x16 grid of 8x8 glyphs.
+    if params.font && (params.width != font::FONT_GRID_SIZE || params.height != font::FONT_GRID_SIZE) {
+        println!("ERROR: Font {} must be a 16x16 grid of 8x8 glyphs ({}x{} pixels), not {}x{}",
+            pathname, font::FONT_GRID_SIZE, font::FONT_GRID_SIZE, params.width, params.height);
+        return None;
+    }
+
+    // Tile order output needs whole tiles.
+    if params.tile_width != 0 &&
+        (!params.width.is_multiple_of(params.tile_width) || !params.height.is_multiple_of(params.tile_height)) {
+        println!("ERROR: The size of {} ({}x{}) is not a multiple of the tile size ({}x{})",
+            pathname, params.width, params.height, params.tile_width, params.tile_height);
+        return None;
+    }
+    Some(params)
+}
+
+// Decode the contents of a PNG file, as RGBA pixels.
+fn decode_image(path: &str, data: &[u8]) -> Option<RgbaImage> {
+    let img = match image::load_from_memory(data) {
+        Ok(img) => img,
+        Err(err) => {
+            println!("ERROR: Cannot decode the specified file ({}): {}", path, err);
+            return None;
+        }
+    };
+    println!("{}, {}x{}, {:?}", path, img.width(), img.height(), img.color());
+
+    match img {
+        image::DynamicImage::ImageRgb8(_) => Some(img.to_rgba8()),
+        image::DynamicImage::ImageRgba8(rgba) => Some(rgba),
+        _ => {
+            println!("ERROR: Unhandled image format ({}). Must be RGB8 or RGBA8!", path);
+            None
+        }
+    }
+}
+
+// Prepare a decoded image for color collection and conversion. When scaling,
+// the crop rectangle (if any) is extracted first, and then resized to the
+// output size, so that the palette reflects the resized pixels.
+fn prepare_image(img_file: &FileParameters, img: RgbaImage) -> RgbaImage {
+    let img = apply_color_key(img_file, img);
+    let img = apply_alpha(img_file, img);
+    match img_file.scale {
+        Some(mode) => {
+            let cropped = match img_file.crop {
+                Some(crop) => image::imageops::crop_imm(&img, crop.x as u32, crop.y as u32,
+                    crop.width as u32, crop.height as u32).to_image(),
+                None => img
+            };
+            let scaled = scale::scale_image(&cropped, mode, img_file.width, img_file.height);
+            println!("Scaled {} from {}x{} to {}x{} ({:?}).", img_file.path,
+                cropped.width(), cropped.height(), scaled.width(), scaled.height(), mode);
+            scaled
+        },
+        None => img
+    }
+}
+
+// Get the prepared image of a file, reading and decoding it unless it is already in memory.
+fn read_prepared_image(img_file: &FileParameters) -> Option<RgbaImage> {
+    if let Some(img) = &img_file.image {
+        return Some(img.clone());
+    }
+    let data = match fs::read(&img_file.path) {
+        Ok(data) => data,
+        Err(err) => {
+            println!("ERROR: Cannot read the specified file ({}): {}", img_file.path, err);
+            return None;
+        }
+    };
+    let img = decode_image(&img_file.path, &data)?;
+    Some(prepare_image(img_file, img))
+}
+
+// Make the pixels that match the color key transparent. The key is matched
+// as a 12-bit color, just as the pixel colors are.
+fn apply_color_key(img_file: &FileParameters, mut img: RgbaImage) -> RgbaImage {
+    let key = match img_file.key {
+        Some(ColorKey::Color(color)) => color,
+        Some(ColorKey::TopLeft) => {
+            match img.pixels().next().and_then(pixel_color) {
+                Some(color) => color,
+                None => return img // already transparent
+            }
+        },
+        None => return img
+    };
+
+    let mut keyed: usize = 0;
+    for pixel in img.pixels_mut() {
+        if pixel_color(pixel) == Some(key) {
+            *pixel = Rgba::<u8>([0, 0, 0, 0]);
+            keyed += 1;
+        }
+    }
+    println!("Made {} pixels of file {} transparent (color key {:x} {:x} {:x}).",
+        keyed, img_file.path, key[0], key[1], key[2]);
+    img
+}
+
+// Pixels with alpha below this are transparent, unless another alpha
+// threshold is given.
+const DEFAULT_ALPHA_THRESHOLD: u8 = 16;
+
+// Make the pixels below the alpha threshold transparent, and blend any
+// semi-transparent pixels against the matte color, if one is given.
+fn apply_alpha(img_file: &FileParameters, mut img: RgbaImage) -> RgbaImage {
+    if img_file.alpha_threshold.is_none() && img_file.matte.is_none() {
+        return img;
+    }
+    let threshold = img_file.alpha_threshold.unwrap_or(DEFAULT_ALPHA_THRESHOLD);
+
+    let mut cleared: usize = 0;
+    let mut blended: usize = 0;
+    let mut kept: usize = 0;
+    for pixel in img.pixels_mut() {
+        let alpha = pixel[IMG_A];
+        if alpha == 0 || alpha == 255 {
+            continue;
+        }
+        if alpha < threshold {
+            *pixel = Rgba::<u8>([0, 0, 0, 0]);
+            cleared += 1;
+        } else if let Some(matte) = img_file.matte {
+            for channel in [IMG_R, IMG_G, IMG_B] {
+                let matte_level = matte[channel] as u32 * 17;
+                pixel[channel] = ((pixel[channel] as u32 * alpha as u32 +
+                    matte_level * (255 - alpha as u32) + 127) / 255) as u8;
+            }
+            pixel[IMG_A] = 255;
+            blended += 1;
+        } else {
+            // Keep it visible, even if its alpha is low.
+            pixel[IMG_A] = alpha.max(DEFAULT_ALPHA_THRESHOLD);
+            kept += 1;
+        }
+    }
+    match img_file.matte {
+        Some(matte) => {
+            println!("File {}: {} pixels below alpha {} made transparent, {} semi-transparent pixels blended against matte {:x} {:x} {:x}.",
+                img_file.path, cleared, threshold, blended, matte[0], matte[1], matte[2]);
+        },
+        None => {
+            println!("File {}: {} pixels below alpha {} made transparent, {} semi-transparent pixels kept.",
+                img_file.path, cleared, threshold, kept);
+        }
+    }
+    img
+}
+
+// Obtain the 12-bit color of a pixel, or None if the pixel is transparent.
+fn pixel_color(pixel: &Rgba<u8>) -> Option<Rgb<u8>> {
+    let a = pixel[IMG_A] >> 4;
+    if a > 0 {
+        let r = pixel[IMG_R] >> 4;
+        let g = pixel[IMG_G] >> 4;
+        let b = pixel[IMG_B] >> 4;
+        Some(Rgb::<u8>([r, g, b]))
+    } else {
+        None
+    }
+}
+
+// Give index 0 to one of the colors of an image that uses an opaque index 0:
+// black, if the image has it, or else the first color found. The colors
+// after it move down by one index. The image must not need transparency.
+fn assign_index_zero(img_file: &mut FileParameters) -> bool {
+    if img_file.transparent {
+        println!("ERROR: File {} has transparent pixels, but uses an opaque index 0", img_file.path);
+        return false;
+    }
+    let zero_index = img_file.colors.get(&Rgb::<u8>([0, 0, 0])).copied().unwrap_or(1);
+    for index in img_file.colors.values_mut() {
+        if *index == zero_index {
+            *index = 0;
+        } else if *index > zero_index {
+            *index -= 1;
+        }
+    }
+    true
+}
+
+// List the colors of a file in the order that they were encountered,
+// so that palette assembly does not depend on hash map ordering.
+fn colors_in_order(colors: &HashMap<Rgb<u8>, u8>) -> Vec<(Rgb<u8>, u8)> {
+    let mut list: Vec<(Rgb<u8>, u8)> = colors.iter().map(|(color, index)| (*color, *index)).collect();
+    list.sort_by_key(|(_color, index)| *index);
+    list
+}
+
+fn assemble_palette(files: &[&FileParameters], cycles: &[CycleRange], options: &Options) -> Option<Palette> {
+    let mode = options.palette_mode;
+    let mut palette_map: HashMap<Rgb<u8>, Vec<u8>> = HashMap::new();
+    let mut offset_map: Vec<HashMap<Rgb<u8>, u8>> = vec![];
+    let mut palette_array: Vec<Option<Rgb::<u8>>> = vec![];
+
+    for _offset in 0..16 {
+        offset_map.push(HashMap::new());
+    }
+
+    for _index in 0..256 {
+        palette_array.push(None);
+    }
+
+    // Insert the default palette colors that the mode keeps (the standard
+    // colors, the whole VERA default palette, or none of them).
+    for (index, entry) in palette_array.iter_mut().enumerate().take(mode.reserved_entries()).skip(1) {
+        *entry = Some(default_palette::default_color(index));
+    }
+
+    // Index 0 holds the opaque color of the files that use it. They must
+    // agree on that color, and no file in the same palette bank (the same
+    // palette offset, or 8 bpp) may need transparency.
+    let mut zero_color: Option<(Rgb<u8>, &str)> = None;
+    for img_file in files.iter().filter(|file| file.opaque_zero) {
+        if let Some(other) = files.iter().find(|file| !file.opaque_zero && file.transparent &&
+                file.palette_offset == img_file.palette_offset) {
+            println!("ERROR: File {} needs transparency, but file {} uses an opaque index 0 in the same palette bank",
+                other.path, img_file.path);
+            return None;
+        }
+        for (color, index) in &img_file.colors {
+            if *index != 0 {
+                continue;
+            }
+            match zero_color {
+                Some((other_color, other_path)) if other_color != *color => {
+                    println!("ERROR: File {} uses color {:x} {:x} {:x} at index 0, but file {} uses {:x} {:x} {:x}",
+                        img_file.path, color[0], color[1], color[2],
+                        other_path, other_color[0], other_color[1], other_color[2]);
+                    return None;
+                },
+                Some(_) => {},
+                None => zero_color = Some((*color, &img_file.path))
+            }
+        }
+    }
+    if let Some((color, _path)) = zero_color {
+        palette_array[0] = Some(color);
+    }
+
+    // Reserve the color cycling ranges, before placing any other colors.
+    for range in cycles {
+        for (slot, color) in range.colors.iter().enumerate() {
+            palette_array[range.start + slot] = Some(*color);
+        }
+    }
+
+    // Consolidate shared palette offset colors.
+    for img_file in files {
+        if let Some(offset) = img_file.palette_offset {
+            for (color, index) in colors_in_order(&img_file.colors) {
+                if index != 0 {
+                    offset_map[offset].entry(color).or_insert(index);
+                }
+            }
+        }
+    }
+
+    // Place colors from palette offsets into the palette array. Offset 0 is
+    // never given (the arguments only allow offsets 1-15).
+    for (offset, colors) in offset_map.iter().enumerate().skip(1) {
+        if mode == PaletteMode::Vera && !colors.is_empty() {
+            println!("NOTE: Palette offset {} replaces part of the VERA default palette.", offset);
+        }
+        for (color, index) in colors {
+            let palette_index = offset * 16 + *index as usize;
+            if let Some(range) = cycles.iter().find(|range| range.start <= palette_index && palette_index < range.end()) {
+                println!("ERROR: Palette offset {} overlaps color cycling range {}-{}",
+                    offset, range.start, range.end() - 1);
+                return None;
+            }
+            palette_array[palette_index] = Some(*color);
+        }
+    }
+
+    // Map each color to the indexes where it appears, lowest index first.
+    for (index, entry) in palette_array.iter().enumerate().skip(1) {
+        if let Some(color) = entry {
+            palette_map.entry(*color).or_default().push(index as u8);
+        }
+    }
+
+    // Find indexes for all non-palette-offset (i.e., 8-bpp) colors.
+    let next_index: usize = mode.reserved_entries();
+    for img_file in files {
+        if img_file.palette_offset.is_none() {
+            for (color, index) in colors_in_order(&img_file.colors) {
+                if index == 0 {
+                    continue; // the opaque index 0
+                }
+                if let Entry::Vacant(entry) = palette_map.entry(color) {
+                    // Reuse a near enough color that is already in the palette. With
+                    // the VERA default palette, the nearest color is always used.
+                    if let Some(metric) = options.metric {
+                        let candidates = palette_array.iter().enumerate().skip(1)
+                            .filter_map(|(index, entry)| entry.as_ref().map(|c| (index, c)));
+                        if let Some((index, d)) = color_match::nearest(metric, &color, candidates) {
+                            if mode == PaletteMode::Vera || d <= options.tolerance.unwrap_or(0.0) {
+                                println!("Color {:x} {:x} {:x} in file {} matches palette index {} (distance {:.2}).",
+                                    color[0], color[1], color[2], img_file.path, index, d);
+                                entry.insert(vec![index as u8]);
+                                continue;
                             }
                         }
-                    },
-                    Err(err) => {
-                        println!("ERROR: Cannot open output file ({}): {}", uc_path, err.to_string());
+                    }
+                    if mode == PaletteMode::Vera {
+                        println!("ERROR: Color {:x} {:x} {:x} in file {} is not in the VERA default palette",
+                            color[0], color[1], color[2], img_file.path);
+                        return None;
+                    }
+                    match (next_index..256).find(|index| palette_array[*index].is_none()) {
+                        Some(palette_index) => {
+                            palette_array[palette_index] = Some(color);
+                            entry.insert(vec![palette_index as u8]);
+                        },
+                        None => {
+                            println!("ERROR: Could not insert all colors into palette (please reduce colors)");
+                            return None;
+                        }
                     }
                 }
-            },
-            _ => {
-                panic!("ERROR: Unhandled image format. Must be RGBA8!");
             }
         }
     }
 
-    // Write the palette data to a file.
-    let mut palette_bytes: Vec<u8> = vec![];
-    // 2-byte address offset
-    palette_bytes.push(0); // dummy address LO
-    palette_bytes.push(0); // dummy address HI
-    // standard and custom colors
-    for index in 0..palette_array.len() {
-        match palette_array[index] {
+    Some(Palette {
+        palette_map,
+        offset_map,
+        palette_array
+    })
+}
+
+// Parse a color given as 3 or 6 hexadecimal digits ("rgb" or "rrggbb").
+// With 6 digits, only the upper digit of each channel is used.
+fn parse_hex_color(text: &str) -> Option<Rgb<u8>> {
+    let digits: Vec<u8> = text.chars().map(|c| c.to_digit(16).map(|d| d as u8)).collect::<Option<Vec<u8>>>()?;
+    match digits.len() {
+        3 => Some(Rgb::<u8>([digits[0], digits[1], digits[2]])),
+        6 => Some(Rgb::<u8>([digits[0], digits[2], digits[4]])),
+        _ => None
+    }
+}
+
+// Encode palette entries as VERA expects them: [ggggbbbb] [----rrrr].
+// FREE entries are black.
+fn encode_palette(palette_array: &[Option<Rgb<u8>>]) -> Vec<u8> {
+    let mut data: Vec<u8> = vec![];
+    for entry in palette_array {
+        match entry {
             Some(color) => {
-                // Output: [ggggbbbb] [----rrrr]
-                palette_bytes.push((color[1]<<4)|color[2]); // G B
-                palette_bytes.push(color[0]); // R
+                data.push((color[1]<<4)|color[2]); // G B
+                data.push(color[0]); // R
             },
             None => {
-                palette_bytes.push(0);
-                palette_bytes.push(0);
+                data.push(0);
+                data.push(0);
             }
         }
     }
+    data
+}
 
-    let uc_path = "PALETTE.BIN".to_string();
-    match fs::File::create(uc_path.clone()) {
-        Ok(mut file) => {
-            match file.write_all(&palette_bytes[..]) {
-                Ok(()) => {
-                    println!("Wrote file ({}) as {} bytes.", uc_path, palette_bytes.len());
-                },
-                Err(err) => {
-                    println!("ERROR: Cannot write palette file ({}): {}", uc_path, err.to_string());
+// Determine the output color index for a given pixel color.
+fn color_index(img_file: &FileParameters, palette: &Palette, color: &Rgb<u8>) -> Option<u8> {
+    if img_file.font {
+        // Black is background; any other color is foreground.
+        return Some(if *color == Rgb::<u8>([0, 0, 0]) { 0 } else { 1 });
+    }
+    if img_file.opaque_zero && img_file.colors.get(color) == Some(&0) {
+        return Some(0);
+    }
+    let index = match img_file.palette_offset {
+        Some(offset) => {
+            // 1/2/4 bpp
+            let mask: u8 = match img_file.bpp {
+                1 => 1,
+                2 => 3,
+                4 => 15,
+                8 => 255,
+                _ => 0
+            };
+            palette.offset_map[offset].get(color).map(|index| index & mask)
+        }
+        None => {
+            // 8bpp
+            palette.palette_map.get(color).map(|indexes| indexes[0])
+        }
+    };
+    if index.is_none() {
+        println!("ERROR: File {} has color {:x} {:x} {:x}, which is not in the palette",
+            img_file.path, color[0], color[1], color[2]);
+    }
+    index
+}
+
+// Compute a hash of the color indexes used by a file, so that we can tell
+// whether its binary data would change.
+fn file_palette_hash(img_file: &FileParameters, palette: &Palette) -> Option<u64> {
+    let mut data: Vec<u8> = vec![img_file.bpp];
+    for (color, _index) in colors_in_order(&img_file.colors) {
+        data.push(color[0]);
+        data.push(color[1]);
+        data.push(color[2]);
+        data.push(color_index(img_file, palette, &color)?);
+    }
+    Some(hash_bytes(&data))
+}
+
+// Accumulates color indexes into packed bytes, based on bits per pixel.
+struct PixelPacker {
+    pub bpp: u8,
+    pub bits_used: u8,
+    pub output_byte: u8,
+    pub data: Vec<u8>
+}
+
+impl PixelPacker {
+    pub fn new(bpp: u8) -> Self {
+        Self {
+            bpp,
+            bits_used: 0,
+            output_byte: 0,
+            data: vec![]
+        }
+    }
+
+    pub fn push(&mut self, index: u8) {
+        if self.bpp == 8 {
+            self.data.push(index);
+        } else {
+            self.output_byte = (self.output_byte << self.bpp) | index;
+            self.bits_used += self.bpp;
+            if self.bits_used >= 8 {
+                self.data.push(self.output_byte);
+                self.output_byte = 0;
+                self.bits_used = 0;
+            }
+        }
+    }
+
+    // Finish the pixel row, so that each row starts on a byte boundary.
+    pub fn finish_row(&mut self) {
+        if self.bits_used > 0 {
+            while self.bits_used < 8 {
+                self.output_byte <<= self.bpp;
+                self.bits_used += self.bpp;
+            }
+            self.data.push(self.output_byte);
+            self.output_byte = 0;
+            self.bits_used = 0;
+        }
+    }
+}
+
+// Convert pixel colors into indexes, placing the input image (or the cropped
+// area of it) over the output according to the anchor.
+fn convert_image(img_file: &FileParameters, img: &RgbaImage, palette: &Palette) -> Option<Vec<u8>> {
+    // Get dimensions for input image.
+    let source = source_rect(img_file, img);
+    let img_width = source.width as i32;
+    let img_height = source.height as i32;
+
+    // Get dimensions for output image.
+    let out_width = img_file.width as i32;
+    let out_height = img_file.height as i32;
+
+    // Compute the input position that corresponds to output position (0,0).
+    let img_start_x = anchor_offset(img_file.anchor.horizontal(), img_width, out_width);
+    let img_start_y = anchor_offset(img_file.anchor.vertical(), img_height, out_height);
+
+    let mut packer = PixelPacker::new(img_file.bpp);
+    for out_y in 0..out_height {
+        let img_y = img_start_y + out_y;
+        for out_x in 0..out_width {
+            let img_x = img_start_x + out_x;
+            if img_y < 0 || img_y >= img_height || img_x < 0 || img_x >= img_width {
+                // output transparent color index (zero)
+                packer.push(0);
+            } else {
+                let pixel = img.get_pixel(source.x as u32 + img_x as u32, source.y as u32 + img_y as u32);
+                match pixel_color(pixel) {
+                    Some(color) => {
+                        // output some color index
+                        packer.push(color_index(img_file, palette, &color)?);
+                    },
+                    None => {
+                        // output transparent color index (zero)
+                        packer.push(0);
+                    }
                 }
             }
-        },
-        Err(err) => {
-            println!("ERROR: Cannot open palette file ({}): {}", uc_path, err.to_string());
+        }
+        packer.finish_row();
+    }
+    Some(packer.data)
+}
+
+// Tell whether any output pixel would be transparent, either because the
+// input pixel is transparent, or because the input does not cover it.
+fn has_transparency(img_file: &FileParameters, img: &RgbaImage) -> bool {
+    let source = source_rect(img_file, img);
+    if source.width < img_file.width || source.height < img_file.height {
+        return true;
+    }
+    let img_start_x = anchor_offset(img_file.anchor.horizontal(), source.width as i32, img_file.width as i32) as usize;
+    let img_start_y = anchor_offset(img_file.anchor.vertical(), source.height as i32, img_file.height as i32) as usize;
+    (0..img_file.height).any(|out_y| (0..img_file.width).any(|out_x| {
+        let pixel = img.get_pixel((source.x + img_start_x + out_x) as u32, (source.y + img_start_y + out_y) as u32);
+        pixel_color(pixel).is_none()
+    }))
+}
+
+// Determine the offset of the input relative to the output, along one axis,
+// where placement is -1 (left/top), 0 (center), or 1 (right/bottom).
+fn anchor_offset(placement: i32, img_size: i32, out_size: i32) -> i32 {
+    match placement {
+        -1 => 0,
+        0 => img_size / 2 - out_size / 2,
+        _ => img_size - out_size
+    }
+}
+
+// Obtain the area of the (prepared) input image that is copied to the output.
+// A scaled image has already been cropped, so all of it is used.
+fn source_rect(img_file: &FileParameters, img: &RgbaImage) -> Rect {
+    match img_file.crop {
+        Some(crop) if img_file.scale.is_none() => crop,
+        _ => Rect {
+            x: 0,
+            y: 0,
+            width: img.width() as usize,
+            height: img.height() as usize
+        }
+    }
+}
+
+// Make sure that the crop rectangle (if any) lies within the input image.
+fn check_crop(img_file: &FileParameters) -> bool {
+    if let Some(crop) = img_file.crop {
+        if crop.x + crop.width > img_file.source_width || crop.y + crop.height > img_file.source_height {
+            println!("ERROR: Crop rectangle {},{},{},{} is outside of file {} ({}x{})",
+                crop.x, crop.y, crop.width, crop.height, img_file.path,
+                img_file.source_width, img_file.source_height);
+            return false;
         }
     }
+    true
+}
 
-    arrange_files_in_memory(&mut files);
+// Write data to a file, unless the file already contains exactly that data,
+// so that build tools do not see unchanged outputs as being modified.
+// Returns true if the file was written.
+fn write_if_changed(path: &str, data: &[u8]) -> std::io::Result<bool> {
+    if let Ok(existing) = fs::read(path) {
+        if existing == data {
+            return Ok(false);
+        }
+    }
+    fs::write(path, data)?;
+    Ok(true)
 }
 
 fn upcase_filename(path: &str) -> String {
-    let parts = path.split("/").collect::<Vec<&str>>();
+    let parts = path.split('/').collect::<Vec<&str>>();
     let mut output_path = String::new();
-    for i in 0..parts.len()-1 {
-        output_path.push_str(parts[i]);
-        output_path.push_str("/");
+    for part in &parts[..parts.len()-1] {
+        output_path.push_str(part);
+        output_path.push('/');
     }
 
-    let parts2 = parts[parts.len()-1].split(".").collect::<Vec<&str>>();
-    for i in 0..parts2.len()-1 {
-        output_path.push_str(&parts2[i].to_ascii_uppercase());
-        output_path.push_str(".");
+    let parts2 = parts[parts.len()-1].split('.').collect::<Vec<&str>>();
+    for part in &parts2[..parts2.len()-1] {
+        output_path.push_str(&part.to_ascii_uppercase());
+        output_path.push('.');
     }
     output_path.push_str("BIN");
 
@@ -941,37 +2316,82 @@ fn upcase_filename(path: &str) -> String {
 }
 
 fn section_filename(path: &str, section: &str) -> String {
-    let parts = path.split("/").collect::<Vec<&str>>();
+    derived_filename(path, &format!("P{}", section), "BIN")
+}
+
+// Build an output file name from an input file name, by upcasing the name,
+// appending a suffix to it, and replacing its extension.
+fn derived_filename(path: &str, suffix: &str, extension: &str) -> String {
+    let parts = path.split('/').collect::<Vec<&str>>();
     let mut output_path = String::new();
-    for i in 0..parts.len()-1 {
-        output_path.push_str(parts[i]);
-        output_path.push_str("/");
+    for part in &parts[..parts.len()-1] {
+        output_path.push_str(part);
+        output_path.push('/');
     }
 
-    let parts2 = parts[parts.len()-1].split(".").collect::<Vec<&str>>();
-    for i in 0..parts2.len()-1 {
+    let parts2 = parts[parts.len()-1].split('.').collect::<Vec<&str>>();
+    for (i, part) in parts2[..parts2.len()-1].iter().enumerate() {
         if i > 0 {
-            output_path.push_str(".");
+            output_path.push('.');
         }
-        output_path.push_str(&parts2[i].to_ascii_uppercase());
+        output_path.push_str(&part.to_ascii_uppercase());
     }
-    output_path.push_str("P");
-    output_path.push_str(section);
-    output_path.push_str(".BIN");
+    output_path.push_str(suffix);
+    output_path.push('.');
+    output_path.push_str(extension);
 
     output_path
 }
 
-fn arrange_files_in_memory(files: &mut Vec<FileParameters>) {
+// Determine whether a PNG file in an input directory was written by this
+// program (a preview or a palette swatch), so that it is not read back in
+// as an input image.
+fn is_generated_file(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path).to_ascii_uppercase();
+    name.ends_with("_PREVIEW.PNG") ||
+        name == "PALETTE.PNG" || (name.starts_with("PALETTE_") && name.ends_with(".PNG"))
+}
+
+// Obtain the name of a palette file, for a palette set.
+fn palette_filename(set_name: &Option<String>, extension: &str) -> String {
+    match set_name {
+        Some(name) => format!("PALETTE_{}.{}", name.to_ascii_uppercase(), extension),
+        None => format!("PALETTE.{}", extension)
+    }
+}
+
+// Obtain the name of a palette fade file, for a palette set.
+fn fade_filename(set_name: &Option<String>, extension: &str) -> String {
+    match set_name {
+        Some(name) => format!("FADE_{}.{}", name.to_ascii_uppercase(), extension),
+        None => format!("FADE.{}", extension)
+    }
+}
+
+// Obtain the prefix for the labels of a palette table, for a palette set.
+fn label_prefix(set_name: &Option<String>) -> String {
+    match set_name {
+        Some(name) => format!("{}_", name),
+        None => String::new()
+    }
+}
+
+// With an overflow list, files that would not fit into VRAM are moved to
+// it, rather than being placed. The reserved areas (such as those of the
+// common files of a scene) are already in use, so the files are arranged
+// around them, but they are not listed again. Pinned files that overlap
+// each other (or a reserved area) cannot be arranged at all.
+fn arrange_files_in_memory(files: &mut Vec<FileParameters>, mut overflow: Option<&mut Vec<FileParameters>>,
+                           reserved: &[Placement], split: &SplitRule) -> Option<Vec<Placement>> {
     // Sort the files based on:
     // - vapor flag (descending)
     // - alignment (descending)
     // - size (descending)
     // - path (ascending)
     files.sort_by(|a,b| {
-        if a.vapor > b.vapor {
+        if a.vapor & !b.vapor {
             Ordering::Less
-        } else if a.vapor < b.vapor {
+        } else if !a.vapor & b.vapor {
             Ordering::Greater
         } else if a.alignment > b.alignment {
             Ordering::Less
@@ -993,10 +2413,50 @@ fn arrange_files_in_memory(files: &mut Vec<FileParameters>) {
     println!("Waste Start  End    Size  Align Width Height Path/Name");
     println!("----- ------ ------ ----- ----- ----- ------ ----------------------------------");
 
+    let mut layout: Vec<Placement> = vec![];
     let mut boundary_crossing = false;
+
+    // Place any files that are pinned to fixed addresses first, so that
+    // the other files can be arranged around them.
+    let mut pinned: Vec<FileParameters> = files.iter()
+        .filter(|file| file.fixed_address.is_some() && !file.no_output)
+        .cloned().collect();
+    pinned.sort_by_key(|file| file.fixed_address);
+    files.retain(|file| file.fixed_address.is_none());
+
+    let mut fixed_ranges: Vec<(usize, usize)> = reserved.iter()
+        .map(|placement| (placement.start, placement.start + placement.size)).collect();
+    for file in &pinned {
+        let start = file.fixed_address.unwrap();
+        let last_address = start + file.size - 1;
+        if fixed_overlap(&fixed_ranges, start, file.size).is_some() {
+            println!("ERROR: Pinned file {} overlaps another pinned file.", file.path);
+            return None;
+        }
+        println!("{:>5} ${:05x} ${:05x} {:5} {:>5} {:5} {:5}  {}",
+            "fixed",
+            start,
+            last_address,
+            file.size,
+            "-",
+            file.width,
+            file.height,
+            layout_name(file));
+
+        boundary_crossing |= write_output_parts(start, file, split);
+
+        layout.push(Placement {
+            path: file.path.clone(),
+            start,
+            size: file.size
+        });
+        fixed_ranges.push((start, start + file.size));
+    }
+    let fixed_end = fixed_ranges.iter().map(|(_start, end)| *end).max().unwrap_or(0);
+
     let mut address: usize = 0;
     loop {
-        if files.len() == 0 {
+        if files.is_empty() {
             break; // no more files to arrange
         }
 
@@ -1008,13 +2468,26 @@ fn arrange_files_in_memory(files: &mut Vec<FileParameters>) {
         }
 
         // Advance the address, if needed, based on alignment.
-        let next_address = ((address + file.alignment - 1) / file.alignment) * file.alignment;
+        let next_address = address.div_ceil(file.alignment) * file.alignment;
         let diff = next_address - address;
 
+        // Skip past any pinned file that is in the way.
+        if let Some(end) = fixed_overlap(&fixed_ranges, next_address, file.size) {
+            address = end;
+            continue;
+        }
+
         if diff == 0 || files.len() == 1 {
             // The current file fits perfectly at the next address,
             // or this is the last file to arrange.
             let last_address = next_address + file.size - 1;
+            if let Some(overflow) = overflow.as_deref_mut().filter(|_| last_address >= VRAM_LIMIT && !file.vapor) {
+                println!("{:>5} {:>6} {:>6} {:5} {:5} {:5} {:5}  {}",
+                    "-", "RAM", "-", file.size, file.alignment, file.width, file.height, layout_name(&file));
+                overflow.push(file);
+                files.remove(0);
+                continue;
+            }
             println!("{:5} ${:05x} ${:05x} {:5} {:5} {:5} {:5}  {}",
                 diff,
                 next_address,
@@ -1023,11 +2496,15 @@ fn arrange_files_in_memory(files: &mut Vec<FileParameters>) {
                 file.alignment,
                 file.width,
                 file.height,
-                file.path);
+                layout_name(&file));
+
+            boundary_crossing |= write_output_parts(next_address, &file, split);
 
-            boundary_crossing |=
-                check_for_vram_page_crossing(next_address,
-                    last_address, &file);
+            layout.push(Placement {
+                path: file.path.clone(),
+                start: next_address,
+                size: file.size
+            });
 
             files.remove(0);
             address = next_address + file.size;
@@ -1038,19 +2515,14 @@ fn arrange_files_in_memory(files: &mut Vec<FileParameters>) {
             let mut best_diff = diff;
             let mut best_address: usize = next_address;
 
-            for i in 1..files.len() {
-                let file2 = files[i].clone();
-
+            for (i, file2) in files.iter().enumerate().skip(1) {
                 // Align this potential next file
-                let next_address2 =
-                    ((address + file2.alignment - 1) / file2.alignment) * file2.alignment;
+                let next_address2 = address.div_ceil(file2.alignment) * file2.alignment;
                 let diff2 = next_address2 - address;
 
                 // Realign the file in question
                 let next_address3 = next_address2 + file2.size;
-                let next_address4 =
-                    ((next_address3 + file.alignment - 1) /
-                        file.alignment) * file.alignment;
+                let next_address4 = next_address3.div_ceil(file.alignment) * file.alignment;
                 let diff4 = next_address4 - next_address3 + diff2;
 
                 if diff4 < best_diff {
@@ -1063,11 +2535,21 @@ fn arrange_files_in_memory(files: &mut Vec<FileParameters>) {
 
             // Reorder the files by using the best fit file next
             let file2 = files[best_index].clone();
+            if let Some(end) = fixed_overlap(&fixed_ranges, best_address, file2.size) {
+                address = end;
+                continue;
+            }
             files.remove(best_index);
 
             // The current file fits perfectly at the next address,
             // or this is the last file to arrange.
             let last_address = best_address + file2.size - 1;
+            if let Some(overflow) = overflow.as_deref_mut().filter(|_| last_address >= VRAM_LIMIT && !file2.vapor) {
+                println!("{:>5} {:>6} {:>6} {:5} {:5} {:5} {:5}  {}",
+                    "-", "RAM", "-", file2.size, file2.alignment, file2.width, file2.height, layout_name(&file2));
+                overflow.push(file2);
+                continue;
+            }
             println!("{:5} ${:05x} ${:05x} {:5} {:5} {:5} {:5}  {}",
                 waste_diff,
                 best_address,
@@ -1076,93 +2558,323 @@ fn arrange_files_in_memory(files: &mut Vec<FileParameters>) {
                 file2.alignment,
                 file2.width,
                 file2.height,
-                file2.path);
+                layout_name(&file2));
 
-            boundary_crossing |=
-                check_for_vram_page_crossing(best_address,
-                    last_address, &file);
+            boundary_crossing |= write_output_parts(best_address, &file2, split);
+
+            layout.push(Placement {
+                path: file2.path.clone(),
+                start: best_address,
+                size: file2.size
+            });
 
             address = best_address + file2.size;
         }
     }
-    if boundary_crossing {
-        println!("");
+    if boundary_crossing && *split == SplitRule::default() {
+        println!();
         println!("NOTE: one output image crosses the VRAM page boundary, so there are now two");
         println!("      extra output files, for loading the data in two sections, if needed.");
+    } else if boundary_crossing {
+        println!();
+        println!("NOTE: some output images cross a split boundary (or exceed the split size), so there");
+        println!("      are extra output files, one per part, for loading the data in sections.");
     }
-    if address > VRAM_LIMIT {
-        println!("");
+    if address.max(fixed_end) > VRAM_LIMIT {
+        println!();
         println!("ERROR: These files will not fit in VRAM together.");
     }
+    Some(layout)
 }
 
-fn check_for_vram_page_crossing(first_address: usize, last_address: usize,
-                                img_file: &FileParameters) -> bool {
-    if first_address < VRAM_PAGE_BOUNDARY && last_address > VRAM_PAGE_BOUNDARY {
-        // Output file data crosses VRAM page boundary.
-        // We need to output 2 extra files to split the data for loading.
-        let bank_0_size = VRAM_PAGE_BOUNDARY - first_address;
-        let bank_1_size = last_address + 1 - VRAM_PAGE_BOUNDARY;
-        let bank_0_first_address = first_address;
-        let bank_0_last_address = VRAM_PAGE_BOUNDARY - 1;
-        let bank_1_first_address = VRAM_PAGE_BOUNDARY;
-        let bank_1_last_address = VRAM_PAGE_BOUNDARY + bank_1_size - 1;
-
-        // Write the 1st part of the data.
-        let mut section_data = img_file.binary[..bank_0_size].to_vec();
-        let mut output_data: Vec<u8> = vec![];
-        output_data.push(0); // dummy address byte
-        output_data.push(0); // dummy address byte
-        output_data.append(&mut section_data);
-        let uc_path = section_filename(&img_file.path, "0");
-        match fs::File::create(uc_path.clone()) {
-            Ok(mut file) => {
-                match file.write_all(&output_data[..]) {
-                    Ok(()) => {
-                        println!("      ${:05x} ${:05x} {:5}                    {}",
-                            bank_0_first_address,
-                            bank_0_last_address,
-                            bank_0_size,
-                            uc_path);
-                    },
-                    Err(err) => {
-                        println!("ERROR: Cannot write output file ({}): {}", uc_path, err.to_string());
-                    }
-                }
+// Find the end of a pinned (fixed) address range that overlaps the given range.
+fn fixed_overlap(fixed_ranges: &[(usize, usize)], start: usize, size: usize) -> Option<usize> {
+    fixed_ranges.iter()
+        .filter(|(fixed_start, fixed_end)| start < *fixed_end && *fixed_start < start + size)
+        .map(|(_fixed_start, fixed_end)| *fixed_end)
+        .max()
+}
+
+// Write the output data of a file in parts, if it crosses a boundary (or
+// is larger than a chunk), and list each part in the layout report.
+fn write_output_parts(first_address: usize, img_file: &FileParameters, split: &SplitRule) -> bool {
+    let parts = split.parts(first_address, img_file.binary.len());
+    if parts.len() < 2 {
+        return false;
+    }
+
+    let mut offset: usize = 0;
+    for (number, (address, size)) in parts.iter().enumerate() {
+        let mut output_data: Vec<u8> = vec![
+            0, // dummy address byte
+            0  // dummy address byte
+        ];
+        output_data.extend_from_slice(&img_file.binary[offset..offset + size]);
+        offset += size;
+        let uc_path = section_filename(&img_file.path, &number.to_string());
+        match write_if_changed(&uc_path, &output_data) {
+            Ok(_) => {
+                println!("      ${:05x} ${:05x} {:5}                    {}",
+                    address,
+                    address + size - 1,
+                    size,
+                    uc_path);
             },
             Err(err) => {
-                println!("ERROR: Cannot open output file ({}): {}", uc_path, err.to_string());
+                println!("ERROR: Cannot write output file ({}): {}", uc_path, err);
             }
         }
-        
-        // Write the 2nd part of the data.
-        let mut section_data = img_file.binary[bank_0_size..].to_vec();
-        let mut output_data: Vec<u8> = vec![];
-        output_data.push(0); // dummy address byte
-        output_data.push(0); // dummy address byte
-        output_data.append(&mut section_data);
-        let uc_path = section_filename(&img_file.path, "1");
-        match fs::File::create(uc_path.clone()) {
-            Ok(mut file) => {
-                match file.write_all(&output_data[..]) {
-                    Ok(()) => {
-                        println!("      ${:05x} ${:05x} {:5}                    {}",
-                            bank_1_first_address,
-                            bank_1_last_address,
-                            bank_1_size,
-                            uc_path);
-                    },
-                    Err(err) => {
-                        println!("ERROR: Cannot write output file ({}): {}", uc_path, err.to_string());
-                    }
-                }
-            },
-            Err(err) => {
-                println!("ERROR: Cannot open output file ({}): {}", uc_path, err.to_string());
-            }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A file of the given size, whose output data holds the low byte of
+    // each offset. The tests of other modules use it too.
+    pub fn test_file(path: &str, size: usize) -> FileParameters {
+        let mut file = FileParameters::new(&DirParameters::current_dir());
+        file.path = path.to_string();
+        file.size = size;
+        file.binary = (0..size).map(|offset| offset as u8).collect();
+        file
+    }
+
+    #[test]
+    fn anchors_choose_the_side_of_each_axis() {
+        let sides = |text: &str| {
+            let anchor = Anchor::parse(text).unwrap();
+            (anchor.horizontal(), anchor.vertical())
+        };
+        assert_eq!(sides("c"), (0, 0));
+        assert_eq!(sides("nw"), (-1, -1));
+        assert_eq!(sides("top"), (0, -1));
+        assert_eq!(sides("ne"), (1, -1));
+        assert_eq!(sides("right"), (1, 0));
+        assert_eq!(sides("se"), (1, 1));
+        assert_eq!(sides("bottom"), (0, 1));
+        assert_eq!(sides("sw"), (-1, 1));
+        assert_eq!(sides("left"), (-1, 0));
+        assert_eq!(Anchor::parse("middle"), None);
+    }
+
+    #[test]
+    fn anchor_offsets_for_a_larger_image() {
+        // 5 columns are left over; centering skips 2 on the left, 3 on the right.
+        assert_eq!(anchor_offset(-1, 9, 4), 0);
+        assert_eq!(anchor_offset(0, 9, 4), 2);
+        assert_eq!(anchor_offset(1, 9, 4), 5);
+        assert_eq!(anchor_offset(0, 5, 2), 1);
+    }
+
+    #[test]
+    fn anchor_offsets_for_a_smaller_image() {
+        // 5 output columns are not covered; centering leaves 2 on the left, 3 on the right.
+        assert_eq!(anchor_offset(-1, 4, 9), 0);
+        assert_eq!(anchor_offset(0, 4, 9), -2);
+        assert_eq!(anchor_offset(1, 4, 9), -5);
+        assert_eq!(anchor_offset(0, 2, 5), -1);
+    }
+
+    #[test]
+    fn anchor_offsets_for_an_equal_size_are_zero() {
+        for placement in [-1, 0, 1] {
+            assert_eq!(anchor_offset(placement, 7, 7), 0);
         }
-        true
-    } else {
-        false
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn crop_rectangles_need_four_numbers_and_an_area() {
+        assert_eq!(Rect::parse("1, 2,30,40"), Some(Rect { x: 1, y: 2, width: 30, height: 40 }));
+        assert_eq!(Rect::parse("1,2,30"), None);
+        assert_eq!(Rect::parse("1,2,0,40"), None);
+        assert_eq!(Rect::parse("1,2,-3,40"), None);
+    }
+
+    #[test]
+    fn hex_colors_keep_the_upper_digit_of_each_channel() {
+        assert_eq!(parse_hex_color("f80"), Some(Rgb::<u8>([15, 8, 0])));
+        assert_eq!(parse_hex_color("FF8800"), Some(Rgb::<u8>([15, 8, 0])));
+        assert_eq!(parse_hex_color("1a2b3c"), Some(Rgb::<u8>([1, 2, 3])));
+    }
+
+    #[test]
+    fn hex_colors_reject_other_lengths_and_digits() {
+        assert_eq!(parse_hex_color(""), None);
+        assert_eq!(parse_hex_color("f8"), None);
+        assert_eq!(parse_hex_color("f800"), None);
+        assert_eq!(parse_hex_color("ff88001"), None);
+        assert_eq!(parse_hex_color("g80"), None);
+        assert_eq!(parse_hex_color("$f80"), None);
+        assert_eq!(parse_hex_color("#ff8800"), None);
+        assert_eq!(parse_hex_color(" f80"), None);
+    }
+
+    fn opaque_image(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_pixel(width, height, Rgba::<u8>([255, 0, 0, 255]))
+    }
+
+    fn sized(width: usize, height: usize) -> FileParameters {
+        let mut file = test_file("image.png", 0);
+        file.width = width;
+        file.height = height;
+        file
+    }
+
+    #[test]
+    fn opaque_images_have_no_transparency() {
+        assert!(!has_transparency(&sized(4, 4), &opaque_image(4, 4)));
+    }
+
+    #[test]
+    fn an_alpha_0_pixel_is_transparent() {
+        let mut img = opaque_image(4, 4);
+        img.put_pixel(3, 2, Rgba::<u8>([255, 0, 0, 0]));
+        assert!(has_transparency(&sized(4, 4), &img));
+    }
+
+    #[test]
+    fn uncovered_output_pixels_are_transparent() {
+        assert!(has_transparency(&sized(5, 4), &opaque_image(4, 4)));
+        assert!(has_transparency(&sized(4, 5), &opaque_image(4, 4)));
+    }
+
+    #[test]
+    fn only_visible_pixels_count() {
+        // A centered 2x2 output shows pixels 1-2 of a 4x4 image.
+        let mut img = opaque_image(4, 4);
+        img.put_pixel(0, 0, Rgba::<u8>([255, 0, 0, 0]));
+        assert!(!has_transparency(&sized(2, 2), &img));
+        img.put_pixel(1, 2, Rgba::<u8>([255, 0, 0, 0]));
+        assert!(has_transparency(&sized(2, 2), &img));
+    }
+
+    const BLACK: Rgb<u8> = Rgb::<u8>([0, 0, 0]);
+    const WHITE: Rgb<u8> = Rgb::<u8>([15, 15, 15]);
+    const RED: Rgb<u8> = Rgb::<u8>([15, 0, 0]);
+
+    fn banked_file(path: &str, offset: usize, colors: &[(Rgb<u8>, u8)]) -> FileParameters {
+        let mut file = test_file(path, 0);
+        file.bpp = 4;
+        file.palette_offset = Some(offset);
+        for (color, index) in colors {
+            file.colors.insert(*color, *index);
+        }
+        file
+    }
+
+    fn opaque_zero_file(path: &str, offset: usize, zero_color: Rgb<u8>) -> FileParameters {
+        let mut file = banked_file(path, offset, &[(zero_color, 0), (RED, 1)]);
+        file.opaque_zero = true;
+        file
+    }
+
+    #[test]
+    fn black_moves_to_index_0() {
+        let mut file = banked_file("a.png", 1, &[(WHITE, 1), (RED, 2), (BLACK, 3)]);
+        assert!(assign_index_zero(&mut file));
+        assert_eq!(colors_in_order(&file.colors), vec![(BLACK, 0), (WHITE, 1), (RED, 2)]);
+    }
+
+    #[test]
+    fn without_black_the_first_color_moves_to_index_0() {
+        let mut file = banked_file("a.png", 1, &[(WHITE, 1), (RED, 2)]);
+        assert!(assign_index_zero(&mut file));
+        assert_eq!(colors_in_order(&file.colors), vec![(WHITE, 0), (RED, 1)]);
+    }
+
+    #[test]
+    fn a_transparent_image_cannot_use_an_opaque_index_0() {
+        let mut file = banked_file("a.png", 1, &[(WHITE, 1), (BLACK, 2)]);
+        file.transparent = true;
+        assert!(!assign_index_zero(&mut file));
+        assert_eq!(colors_in_order(&file.colors), vec![(WHITE, 1), (BLACK, 2)]);
+    }
+
+    #[test]
+    fn the_opaque_color_goes_to_palette_index_0() {
+        let a = opaque_zero_file("a.png", 1, WHITE);
+        let b = opaque_zero_file("b.png", 2, WHITE);
+        let palette = assemble_palette(&[&a, &b], &[], &Options::default()).unwrap();
+        assert_eq!(palette.palette_array[0], Some(WHITE));
+        assert_eq!(palette.palette_array[17], Some(RED));
+    }
+
+    #[test]
+    fn files_must_agree_on_the_index_0_color() {
+        let a = opaque_zero_file("a.png", 1, BLACK);
+        let b = opaque_zero_file("b.png", 2, WHITE);
+        assert!(assemble_palette(&[&a, &b], &[], &Options::default()).is_none());
+    }
+
+    #[test]
+    fn transparency_is_not_available_in_the_bank_of_an_opaque_index_0() {
+        let a = opaque_zero_file("a.png", 1, BLACK);
+        let mut c = banked_file("c.png", 1, &[(RED, 1)]);
+        c.transparent = true;
+        assert!(assemble_palette(&[&a, &c], &[], &Options::default()).is_none());
+        c.palette_offset = Some(2);
+        assert!(assemble_palette(&[&a, &c], &[], &Options::default()).is_some());
+    }
+
+    #[test]
+    fn files_that_do_not_fit_overflow_into_banked_ram() {
+        let mut files = vec![test_file("a", 0x10000), test_file("b", 0x10000), test_file("c", 0x100)];
+        let mut overflow: Vec<FileParameters> = vec![];
+        let layout = arrange_files_in_memory(&mut files, Some(&mut overflow), &[], &SplitRule::default()).unwrap();
+        assert_eq!(layout.iter().map(|placement| (placement.path.as_str(), placement.start)).collect::<Vec<_>>(),
+            vec![("a", 0), ("c", 0x10000)]);
+        assert_eq!(overflow.iter().map(|file| file.path.as_str()).collect::<Vec<_>>(), vec!["b"]);
+    }
+
+    #[test]
+    fn without_overflow_every_file_is_placed() {
+        let mut files = vec![test_file("a", 0x10000), test_file("b", 0x10000)];
+        let layout = arrange_files_in_memory(&mut files, None, &[], &SplitRule::default()).unwrap();
+        assert_eq!(layout.len(), 2);
+        assert_eq!(layout[1].start, 0x10000);
+    }
+
+    #[test]
+    fn files_are_arranged_around_reserved_areas() {
+        let reserved = vec![Placement { path: "common".to_string(), start: 0, size: 0x800 }];
+        let mut files = vec![test_file("a", 0x100)];
+        let layout = arrange_files_in_memory(&mut files, None, &reserved, &SplitRule::default()).unwrap();
+        assert_eq!(layout.len(), 1);
+        assert_eq!(layout[0].start, 0x800);
+    }
+
+    #[test]
+    fn previews_are_not_input_images() {
+        assert!(is_generated_file("./art/SEQ08_PREVIEW.PNG"));
+        assert!(is_generated_file("seq08_preview.png"));
+        assert!(!is_generated_file("./art/seq08.png"));
+        assert!(!is_generated_file("./preview/seq08.png"));
+    }
+
+    #[test]
+    fn palette_swatches_are_not_input_images() {
+        assert!(is_generated_file("./PALETTE.PNG"));
+        assert!(is_generated_file("./PALETTE_LEVEL1.PNG"));
+        assert!(!is_generated_file("./palettes.png"));
+        assert!(!is_generated_file("./level1_palette.png"));
+    }
+
+    #[test]
+    fn pinned_files_must_not_overlap() {
+        let pinned = |path: &str, address: usize| {
+            let mut file = test_file(path, 0x800);
+            file.fixed_address = Some(address);
+            file
+        };
+        let mut files = vec![pinned("a", 0x1000), pinned("b", 0x1800)];
+        assert!(arrange_files_in_memory(&mut files, None, &[], &SplitRule::default()).is_some());
+        let mut files = vec![pinned("a", 0x1000), pinned("b", 0x1400)];
+        assert!(arrange_files_in_memory(&mut files, None, &[], &SplitRule::default()).is_none());
+        let reserved = vec![Placement { path: "common".to_string(), start: 0x1000, size: 0x100 }];
+        let mut files = vec![pinned("a", 0x1000)];
+        assert!(arrange_files_in_memory(&mut files, None, &reserved, &SplitRule::default()).is_none());
+    }
+}
@@ -0,0 +1,65 @@
+// Preview rendering.
+//
+// Converts binary output data (packed color indexes) back into RGBA pixels,
+// using the generated 12-bit palette, so that the result looks just like
+// what the X16 will display. Color index 0 is shown as transparent, unless
+// the image uses an opaque color at index 0.
+//
+// Copyright 2023 by Curtis Whitley
+
+use std::io::Cursor;
+use image::{DynamicImage, ImageOutputFormat, Rgb, Rgba, RgbaImage};
+
+// Render packed output data, given the output dimensions, bits per pixel,
+// palette offset (for 1/2/4 bpp), and whether index 0 is opaque, as an RGBA
+// image.
+pub fn render(binary: &[u8], width: usize, height: usize, bpp: u8, palette_offset: Option<usize>,
+              opaque_zero: bool, palette_array: &[Option<Rgb<u8>>]) -> RgbaImage {
+    let mut img = RgbaImage::new(width as u32, height as u32);
+    let row_size = (width * bpp as usize).div_ceil(8);
+    let mask: u8 = ((1u16 << bpp) - 1) as u8;
+
+    for y in 0..height {
+        for x in 0..width {
+            let bit_offset = x * bpp as usize;
+            let position = y * row_size + bit_offset / 8;
+            if position >= binary.len() {
+                continue; // leave it transparent
+            }
+            let shift = 8 - bpp as usize - (bit_offset % 8);
+            let value = (binary[position] >> shift) & mask;
+            if value == 0 {
+                if opaque_zero {
+                    img.put_pixel(x as u32, y as u32, expand_color(&palette_array[0]));
+                }
+                continue; // transparent, or the opaque index 0 (never offset)
+            }
+
+            // VERA adds the palette offset to non-zero color indexes.
+            let index = match palette_offset {
+                Some(offset) => offset * 16 + value as usize,
+                None => value as usize
+            };
+            img.put_pixel(x as u32, y as u32, expand_color(&palette_array[index]));
+        }
+    }
+    img
+}
+
+// Expand a 12-bit palette color into a 32-bit RGBA color. Unused (FREE)
+// palette entries are black, as written to the palette file.
+pub fn expand_color(color: &Option<Rgb<u8>>) -> Rgba<u8> {
+    match color {
+        Some(c) => Rgba::<u8>([c[0] * 17, c[1] * 17, c[2] * 17, 255]),
+        None => Rgba::<u8>([0, 0, 0, 255])
+    }
+}
+
+// Encode an image as PNG file data.
+pub fn encode_png(img: &RgbaImage) -> Option<Vec<u8>> {
+    let mut data: Vec<u8> = vec![];
+    match DynamicImage::ImageRgba8(img.clone()).write_to(&mut Cursor::new(&mut data), ImageOutputFormat::Png) {
+        Ok(()) => Some(data),
+        Err(_) => None
+    }
+}
@@ -0,0 +1,117 @@
+// Image resizing.
+//
+// Input images may be stretched or shrunk to the output size, before their
+// colors are collected. The smooth filters blend neighboring pixels, and so
+// may introduce new colors. The integer scaler only repeats or skips whole
+// pixels, by the same whole factor in both directions, which keeps pixel art
+// crisp; its result may be smaller than the output, and is then padded.
+//
+// Copyright 2023 by Curtis Whitley
+
+use image::RgbaImage;
+use image::imageops::{self, FilterType};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScaleMode {
+    Nearest,
+    Bilinear,
+    Lanczos,
+    Integer
+}
+
+impl ScaleMode {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "nearest" => Some(ScaleMode::Nearest),
+            "bilinear" => Some(ScaleMode::Bilinear),
+            "lanczos" => Some(ScaleMode::Lanczos),
+            "integer" => Some(ScaleMode::Integer),
+            _ => None
+        }
+    }
+}
+
+// Resize an image to the given size, using the given mode.
+pub fn scale_image(img: &RgbaImage, mode: ScaleMode, width: usize, height: usize) -> RgbaImage {
+    let width = width as u32;
+    let height = height as u32;
+    match mode {
+        ScaleMode::Nearest => imageops::resize(img, width, height, FilterType::Nearest),
+        ScaleMode::Bilinear => imageops::resize(img, width, height, FilterType::Triangle),
+        ScaleMode::Lanczos => imageops::resize(img, width, height, FilterType::Lanczos3),
+        ScaleMode::Integer => integer_scale(img, width, height)
+    }
+}
+
+// Enlarge an image by the largest whole factor that fits within the given
+// size, or shrink it by the smallest whole factor that makes it fit.
+fn integer_scale(img: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+    let img_width = img.width();
+    let img_height = img.height();
+
+    if width >= img_width && height >= img_height {
+        let factor = (width / img_width).min(height / img_height).max(1);
+        RgbaImage::from_fn(img_width * factor, img_height * factor, |x, y| {
+            *img.get_pixel(x / factor, y / factor)
+        })
+    } else {
+        let factor = img_width.div_ceil(width).max(img_height.div_ceil(height));
+        RgbaImage::from_fn(img_width.div_ceil(factor), img_height.div_ceil(factor), |x, y| {
+            *img.get_pixel(x * factor, y * factor)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    // An image where every pixel has its own color.
+    fn distinct_image(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| Rgba::<u8>([x as u8 * 16, y as u8 * 16, 0, 255]))
+    }
+
+    #[test]
+    fn enlarging_repeats_each_pixel() {
+        let img = distinct_image(3, 2);
+        for factor in [2, 3] {
+            let scaled = integer_scale(&img, 3 * factor, 2 * factor);
+            assert_eq!(scaled.dimensions(), (3 * factor, 2 * factor));
+            for (x, y, pixel) in scaled.enumerate_pixels() {
+                assert_eq!(pixel, img.get_pixel(x / factor, y / factor), "{}x at {},{}", factor, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn the_factor_fits_both_directions() {
+        // Room for 4x across, but only 2x down.
+        let scaled = integer_scale(&distinct_image(3, 2), 12, 5);
+        assert_eq!(scaled.dimensions(), (6, 4));
+    }
+
+    #[test]
+    fn less_than_twice_the_size_leaves_the_image_alone() {
+        let img = distinct_image(3, 2);
+        assert_eq!(integer_scale(&img, 3, 2), img);
+        assert_eq!(integer_scale(&img, 5, 3), img);
+    }
+
+    #[test]
+    fn shrinking_keeps_every_nth_pixel() {
+        let img = distinct_image(6, 4);
+        let scaled = integer_scale(&img, 3, 3);
+        assert_eq!(scaled.dimensions(), (3, 2));
+        for (x, y, pixel) in scaled.enumerate_pixels() {
+            assert_eq!(pixel, img.get_pixel(x * 2, y * 2));
+        }
+    }
+
+    #[test]
+    fn modes_are_named() {
+        assert_eq!(ScaleMode::parse("integer"), Some(ScaleMode::Integer));
+        assert_eq!(ScaleMode::parse("lanczos"), Some(ScaleMode::Lanczos));
+        assert_eq!(ScaleMode::parse("cubic"), None);
+    }
+}
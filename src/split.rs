@@ -0,0 +1,105 @@
+// Output splitting.
+//
+// The output data of a file may be split into parts, so that it can be
+// loaded in sections: at each multiple of a boundary address (by default,
+// the VRAM page boundary at $10000), and also every so many bytes from the
+// start of the data (such as the most that a loader reads at once, or the
+// 8 KB of a RAM bank). Each part is written as its own file, with its own
+// 2-byte dummy address, and is listed in the layout report.
+//
+// Copyright 2023 by Curtis Whitley
+
+pub const DEFAULT_BOUNDARY: usize = 0x10000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SplitRule {
+    pub boundary: usize,
+    pub chunk_size: Option<usize>
+}
+
+impl Default for SplitRule {
+    fn default() -> Self {
+        SplitRule { boundary: DEFAULT_BOUNDARY, chunk_size: None }
+    }
+}
+
+impl SplitRule {
+    // Divide data of the given size, placed at the given address, into
+    // parts, each given as its address and size.
+    pub fn parts(&self, start: usize, size: usize) -> Vec<(usize, usize)> {
+        let mut parts: Vec<(usize, usize)> = vec![];
+        let mut offset: usize = 0;
+        while offset < size {
+            let address = start + offset;
+            let mut length = (address / self.boundary + 1) * self.boundary - address;
+            if let Some(chunk_size) = self.chunk_size {
+                length = length.min(chunk_size - offset % chunk_size);
+            }
+            length = length.min(size - offset);
+            parts.push((address, length));
+            offset += length;
+        }
+        parts
+    }
+}
+
+// Parse a size, given in decimal, or in hexadecimal after '$' or "0x".
+pub fn parse_size(text: &str) -> Option<usize> {
+    let number = if let Some(hex) = text.strip_prefix('$').or_else(|| text.strip_prefix("0x")) {
+        usize::from_str_radix(hex, 16).ok()?
+    } else {
+        text.parse::<usize>().ok()?
+    };
+    if number > 0 { Some(number) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_rule_splits_only_at_the_vram_page_boundary() {
+        let rule = SplitRule::default();
+        assert_eq!(rule.parts(0x0ff00, 0x100), vec![(0x0ff00, 0x100)]);
+        assert_eq!(rule.parts(0x0ff00, 0x101), vec![(0x0ff00, 0x100), (0x10000, 1)]);
+        assert_eq!(rule.parts(0x10000, 0x2000), vec![(0x10000, 0x2000)]);
+    }
+
+    #[test]
+    fn boundary_splits_at_every_multiple() {
+        let rule = SplitRule { boundary: 0x2000, chunk_size: None };
+        assert_eq!(rule.parts(0x1800, 0x5000),
+            vec![(0x1800, 0x800), (0x2000, 0x2000), (0x4000, 0x2000), (0x6000, 0x800)]);
+        assert_eq!(rule.parts(0x2000, 0x4000), vec![(0x2000, 0x2000), (0x4000, 0x2000)]);
+    }
+
+    #[test]
+    fn chunks_are_counted_from_the_start_of_the_data() {
+        let rule = SplitRule { boundary: DEFAULT_BOUNDARY, chunk_size: Some(0x1000) };
+        assert_eq!(rule.parts(0x100, 0x2000), vec![(0x100, 0x1000), (0x1100, 0x1000)]);
+        assert_eq!(rule.parts(0x100, 0x1000), vec![(0x100, 0x1000)]);
+        assert_eq!(rule.parts(0x100, 0x1001), vec![(0x100, 0x1000), (0x1100, 1)]);
+    }
+
+    #[test]
+    fn boundary_and_chunk_cuts_combine() {
+        let rule = SplitRule { boundary: DEFAULT_BOUNDARY, chunk_size: Some(0x2000) };
+        assert_eq!(rule.parts(0xf000, 0x4000),
+            vec![(0xf000, 0x1000), (0x10000, 0x1000), (0x11000, 0x2000)]);
+    }
+
+    #[test]
+    fn empty_data_has_no_parts() {
+        assert!(SplitRule::default().parts(0x1234, 0).is_empty());
+    }
+
+    #[test]
+    fn sizes_are_decimal_or_hexadecimal() {
+        assert_eq!(parse_size("8192"), Some(8192));
+        assert_eq!(parse_size("$2000"), Some(0x2000));
+        assert_eq!(parse_size("0x10000"), Some(0x10000));
+        assert_eq!(parse_size("0"), None);
+        assert_eq!(parse_size("$"), None);
+        assert_eq!(parse_size("8k"), None);
+    }
+}
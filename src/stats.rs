@@ -0,0 +1,236 @@
+// Color usage and palette statistics report.
+//
+// Lists, for each image, its unique 12-bit colors and how many pixels use
+// each of them, along with how many distinct 24-bit source colors were
+// reduced to each 12-bit color. It then shows which colors are shared by
+// several images, how many palette slots remain free in each 16-color
+// bank, and which files to reduce when the colors do not all fit.
+//
+// Copyright 2023 by Curtis Whitley
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use image::{Rgb, RgbaImage};
+use crate::default_palette::{PaletteMode, default_color};
+use crate::{Palette, Rect, pixel_color};
+
+// Colors covering less than this share of an image's pixels are "rare",
+// and are good candidates for merging into nearby colors.
+const RARE_COLOR_SHARE: f64 = 0.01;
+
+// How one 12-bit color is used in one image.
+#[derive(Debug, Clone)]
+pub struct ColorCount {
+    pub color: Rgb<u8>,
+    pub pixels: usize,
+    pub sources: usize
+}
+
+// How the colors are used in one image.
+#[derive(Debug, Clone)]
+pub struct ColorUsage {
+    pub path: String,
+    pub bpp: u8,
+    pub palette_offset: Option<usize>,
+    pub max_colors: usize,
+    pub pixels: usize,
+    pub colors: Vec<ColorCount>
+}
+
+// Count the pixels of each color within an area of an image.
+pub fn collect_usage(path: &str, bpp: u8, palette_offset: Option<usize>, max_colors: usize,
+                     img: &RgbaImage, rect: &Rect) -> ColorUsage {
+    let mut pixels: usize = 0;
+    let mut counts: HashMap<Rgb<u8>, (usize, HashSet<[u8; 3]>)> = HashMap::new();
+    for y in rect.y..rect.y + rect.height {
+        for x in rect.x..rect.x + rect.width {
+            let pixel = img.get_pixel(x as u32, y as u32);
+            if let Some(color) = pixel_color(pixel) {
+                let count = counts.entry(color).or_default();
+                count.0 += 1;
+                count.1.insert([pixel[0], pixel[1], pixel[2]]);
+                pixels += 1;
+            }
+        }
+    }
+
+    // Most used colors first.
+    let mut colors: Vec<ColorCount> = counts.into_iter()
+        .map(|(color, (pixels, sources))| ColorCount { color, pixels, sources: sources.len() })
+        .collect();
+    colors.sort_by(|a, b| b.pixels.cmp(&a.pixels).then(a.color.0.cmp(&b.color.0)));
+
+    ColorUsage {
+        path: path.to_string(),
+        bpp,
+        palette_offset,
+        max_colors,
+        pixels,
+        colors
+    }
+}
+
+// Print the report. The palette is missing if it could not be assembled.
+pub fn print_report(usages: &[ColorUsage], palette: Option<&Palette>, mode: PaletteMode) {
+    println!("\nColor Usage Report\n");
+
+    // Which images use each color.
+    let mut users: BTreeMap<[u8; 3], BTreeSet<usize>> = BTreeMap::new();
+    for (file_index, usage) in usages.iter().enumerate() {
+        for count in &usage.colors {
+            users.entry(count.color.0).or_default().insert(file_index);
+        }
+    }
+
+    for usage in usages {
+        let unique = usage.colors.iter().filter(|count| users[&count.color.0].len() == 1).count();
+        let sources: usize = usage.colors.iter().map(|count| count.sources).sum();
+        println!("File {} ({} bpp{}): {} colors (maximum is {}), {} unique to this file, {} pixels, {} source colors",
+            usage.path, usage.bpp, offset_text(usage.palette_offset), usage.colors.len(),
+            usage.max_colors, unique, usage.pixels, sources);
+        println!("    Color   Pixels Sources Used by");
+        for count in &usage.colors {
+            let used_by = users[&count.color.0].len();
+            println!("    {:x} {:x} {:x} {:8} {:7} {}",
+                count.color[0], count.color[1], count.color[2], count.pixels, count.sources,
+                if used_by == 1 { "this file only".to_string() } else { format!("{} files", used_by) });
+        }
+        println!();
+    }
+
+    // Colors shared by several images.
+    let shared: Vec<(&[u8; 3], &BTreeSet<usize>)> = users.iter().filter(|(_color, files)| files.len() > 1).collect();
+    println!("{} colors in all, {} shared by two or more files, {} used by only one file.",
+        users.len(), shared.len(), users.len() - shared.len());
+    for (color, files) in &shared {
+        let names: Vec<&str> = files.iter().map(|index| usages[*index].path.as_str()).collect();
+        println!("    {:x} {:x} {:x}  {}", color[0], color[1], color[2], names.join(", "));
+    }
+    println!();
+
+    // 12-bit colors that several 24-bit colors were reduced to.
+    let mut collapsed: BTreeMap<[u8; 3], usize> = BTreeMap::new();
+    for usage in usages {
+        for count in usage.colors.iter().filter(|count| count.sources > 1) {
+            let entry = collapsed.entry(count.color.0).or_default();
+            *entry = (*entry).max(count.sources);
+        }
+    }
+    println!("{} colors were reduced from two or more 24-bit colors.", collapsed.len());
+    for (color, sources) in &collapsed {
+        println!("    {:x} {:x} {:x}  <- {} colors", color[0], color[1], color[2], sources);
+    }
+    println!();
+
+    // Free palette slots, per bank. Without a palette, show the slots that
+    // are free before the 8 bpp colors are placed.
+    let (used, placed) = match palette {
+        Some(palette) => (palette.palette_array.iter().map(|entry| entry.is_some()).collect(), true),
+        None => (slots_before_8bpp(usages, mode), false)
+    };
+    if placed {
+        println!("Free palette slots per 16-color bank:");
+    } else {
+        println!("Free palette slots per 16-color bank, before placing 8 bpp colors:");
+    }
+    let mut free_total = 0;
+    for bank in 0..16 {
+        // Index 0 is reserved for transparency.
+        let free = (bank * 16..bank * 16 + 16).filter(|index| *index != 0 && !used[*index]).count();
+        free_total += free;
+        println!("    bank {:2} (${:02x}-${:02x}): {:2} free", bank, bank * 16, bank * 16 + 15, free);
+    }
+    println!("    total: {} free\n", free_total);
+
+    print_suggestions(usages, &users, &used, placed, free_total, mode);
+}
+
+// Mark the palette slots that are filled by the palette mode and by files
+// with palette offsets.
+fn slots_before_8bpp(usages: &[ColorUsage], mode: PaletteMode) -> Vec<bool> {
+    let mut used: Vec<bool> = vec![false; 256];
+    for slot in used.iter_mut().take(mode.reserved_entries()).skip(1) {
+        *slot = true;
+    }
+    for usage in usages {
+        if let Some(offset) = usage.palette_offset {
+            if offset == 0 && mode != PaletteMode::None {
+                continue; // uses the standard colors
+            }
+            for index in 1..=usage.colors.len().min(15) {
+                used[offset * 16 + index] = true;
+            }
+        }
+    }
+    used
+}
+
+fn print_suggestions(usages: &[ColorUsage], users: &BTreeMap<[u8; 3], BTreeSet<usize>>,
+                     used: &[bool], placed: bool, free_total: usize, mode: PaletteMode) {
+    println!("Suggestions:");
+    let mut suggestions = 0;
+
+    // Files that have too many colors for their bits per pixel.
+    for usage in usages.iter().filter(|usage| usage.colors.len() > usage.max_colors) {
+        println!("    Reduce {} by at least {} colors; at {} bpp it may have at most {}.",
+            usage.path, usage.colors.len() - usage.max_colors, usage.bpp, usage.max_colors);
+        suggestions += 1;
+    }
+
+    // 8 bpp colors that need palette slots of their own.
+    let needed: BTreeSet<[u8; 3]> = usages.iter()
+        .filter(|usage| usage.palette_offset.is_none())
+        .flat_map(|usage| usage.colors.iter().map(|count| count.color.0))
+        .filter(|color| !in_fixed_slots(color, used, mode))
+        .collect();
+    if mode == PaletteMode::Vera && !placed && !needed.is_empty() {
+        println!("    {} 8 bpp colors are not in the VERA default palette; use '-m' to map them to the nearest colors.",
+            needed.len());
+        suggestions += 1;
+    } else if !placed && needed.len() > free_total {
+        let excess = needed.len() - free_total;
+        println!("    The 8 bpp files need {} palette slots, but only {} are free; remove at least {} colors.",
+            needed.len(), free_total, excess);
+
+        // The files whose own colors would free the most slots.
+        let mut candidates: Vec<(&ColorUsage, usize)> = usages.iter()
+            .filter(|usage| usage.palette_offset.is_none())
+            .map(|usage| (usage, usage.colors.iter()
+                .filter(|count| users[&count.color.0].len() == 1 && needed.contains(&count.color.0))
+                .count()))
+            .filter(|(_usage, unique)| *unique > 0)
+            .collect();
+        candidates.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.path.cmp(&b.0.path)));
+        let mut freed = 0;
+        for (usage, unique) in candidates {
+            let rare = usage.colors.iter()
+                .filter(|count| (count.pixels as f64) < usage.pixels as f64 * RARE_COLOR_SHARE)
+                .count();
+            println!("    Reduce or quantize {}: {} of its colors are used by no other file, and {} cover under 1% of its pixels.",
+                usage.path, unique, rare);
+            suggestions += 1;
+            freed += unique;
+            if freed >= excess {
+                break;
+            }
+        }
+        println!("    Colors within a small distance of each other may also be merged by using '-m' and '-tolerance'.");
+        suggestions += 1;
+    }
+
+    if suggestions == 0 {
+        println!("    (none; all colors fit, with {} palette slots free)", free_total);
+    }
+    println!();
+}
+
+// Tell whether a color is already in a slot that the palette mode keeps.
+fn in_fixed_slots(color: &[u8; 3], used: &[bool], mode: PaletteMode) -> bool {
+    (1..mode.reserved_entries()).any(|index| used[index] && default_color(index).0 == *color)
+}
+
+fn offset_text(palette_offset: Option<usize>) -> String {
+    match palette_offset {
+        Some(offset) => format!(", offset {}", offset),
+        None => String::new()
+    }
+}
@@ -0,0 +1,131 @@
+// Tile ordering.
+//
+// Pixel data is normally output in rows across the whole image width, but
+// VERA tile layers expect the bytes of each tile to be contiguous. In tile
+// order, the tiles of an image are stored left-to-right, top-to-bottom, and
+// each tile is stored as its own rows of packed pixels.
+//
+// Copyright 2023 by Curtis Whitley
+
+// Parse a tile size, such as "8", "16", or "8x16" (width x height).
+pub fn parse_tile_size(text: &str) -> Option<(usize, usize)> {
+    let parts = text.split('x').collect::<Vec<&str>>();
+    let (width, height) = match parts.len() {
+        1 => (parts[0].parse::<usize>().ok()?, parts[0].parse::<usize>().ok()?),
+        2 => (parts[0].parse::<usize>().ok()?, parts[1].parse::<usize>().ok()?),
+        _ => return None
+    };
+    if (width == 8 || width == 16) && (height == 8 || height == 16) {
+        Some((width, height))
+    } else {
+        None
+    }
+}
+
+// Convert row-major data into tile-ordered data, given the width of the
+// whole image in pixels. The image must consist of whole tiles.
+pub fn to_tile_order(data: &[u8], width: usize, tile_width: usize,
+                     tile_height: usize, bpp: u8) -> Vec<u8> {
+    let tile_row_size = tile_width * bpp as usize / 8;
+    let row_size = width * bpp as usize / 8;
+    let tiles_per_row = width / tile_width;
+    let tile_rows = data.len() / row_size / tile_height;
+
+    let mut output: Vec<u8> = Vec::with_capacity(data.len());
+    for tile_y in 0..tile_rows {
+        for tile_x in 0..tiles_per_row {
+            for y in 0..tile_height {
+                let position = (tile_y * tile_height + y) * row_size + tile_x * tile_row_size;
+                output.extend_from_slice(&data[position..position + tile_row_size]);
+            }
+        }
+    }
+    output
+}
+
+// Convert tile-ordered data into row-major data, given the width of the
+// whole image in pixels. If the last row of tiles is incomplete, it is
+// filled with zero bytes (transparent pixels).
+pub fn from_tile_order(data: &[u8], width: usize, tile_width: usize,
+                       tile_height: usize, bpp: u8) -> Vec<u8> {
+    let tile_row_size = tile_width * bpp as usize / 8;
+    let tile_size = tile_row_size * tile_height;
+    let row_size = width * bpp as usize / 8;
+    let tiles_per_row = width / tile_width;
+    let tile_count = data.len().div_ceil(tile_size);
+    let tile_rows = tile_count.div_ceil(tiles_per_row);
+
+    let mut output: Vec<u8> = vec![0; tile_rows * tile_height * row_size];
+    for (tile, tile_data) in data.chunks(tile_size).enumerate() {
+        let tile_x = tile % tiles_per_row;
+        let tile_y = tile / tiles_per_row;
+        for (y, tile_row) in tile_data.chunks(tile_row_size).enumerate() {
+            let position = (tile_y * tile_height + y) * row_size + tile_x * tile_row_size;
+            output[position..position + tile_row.len()].copy_from_slice(tile_row);
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Row-major data where each byte holds its own offset.
+    fn numbered(size: usize) -> Vec<u8> {
+        (0..size).map(|offset| offset as u8).collect()
+    }
+
+    #[test]
+    fn tile_sizes_are_8_or_16() {
+        assert_eq!(parse_tile_size("8"), Some((8, 8)));
+        assert_eq!(parse_tile_size("8x16"), Some((8, 16)));
+        assert_eq!(parse_tile_size("16x8"), Some((16, 8)));
+        assert_eq!(parse_tile_size("32"), None);
+        assert_eq!(parse_tile_size("8x8x8"), None);
+        assert_eq!(parse_tile_size("x8"), None);
+    }
+
+    #[test]
+    fn tiles_are_stored_contiguously() {
+        // 16x8 pixels at 8 bpp: two 8x8 tiles side by side.
+        let data = numbered(16 * 8);
+        let tiled = to_tile_order(&data, 16, 8, 8, 8);
+        assert_eq!(&tiled[0..8], &data[0..8]);
+        assert_eq!(&tiled[8..16], &data[16..24]);
+        assert_eq!(&tiled[64..72], &data[8..16]);
+    }
+
+    #[test]
+    fn tile_order_roundtrips_at_every_depth() {
+        for bpp in [1u8, 2, 4, 8] {
+            for (tile_width, tile_height) in [(8, 8), (16, 16), (8, 16), (16, 8)] {
+                let width = tile_width * 3;
+                let height = tile_height * 2;
+                let data = numbered(width * bpp as usize / 8 * height);
+                let tiled = to_tile_order(&data, width, tile_width, tile_height, bpp);
+                assert_eq!(tiled.len(), data.len());
+                assert_eq!(from_tile_order(&tiled, width, tile_width, tile_height, bpp), data,
+                    "{} bpp, {}x{} tiles", bpp, tile_width, tile_height);
+            }
+        }
+    }
+
+    #[test]
+    fn one_bpp_tiles_use_one_byte_per_row() {
+        // 16x8 pixels at 1 bpp: 2 bytes per image row, 1 byte per tile row.
+        let data = numbered(2 * 8);
+        let tiled = to_tile_order(&data, 16, 8, 8, 1);
+        assert_eq!(tiled, vec![0, 2, 4, 6, 8, 10, 12, 14, 1, 3, 5, 7, 9, 11, 13, 15]);
+    }
+
+    #[test]
+    fn an_incomplete_tile_row_is_filled_with_zeros() {
+        // Three 8x8 tiles (at 8 bpp) in an image two tiles wide.
+        let tiled = numbered(3 * 64);
+        let rows = from_tile_order(&tiled, 16, 8, 8, 8);
+        assert_eq!(rows.len(), 16 * 16);
+        assert_eq!(&rows[128..136], &tiled[128..136]);
+        assert!(rows[136..144].iter().all(|byte| *byte == 0));
+    }
+}
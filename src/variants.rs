@@ -0,0 +1,87 @@
+// Recolor variants.
+//
+// A recolor variant is an image that looks exactly like a base image,
+// except for its colors. Its own output is not written; instead, its
+// colors are placed at its own palette offset, so that the base image's
+// output shows the variant's colors when drawn with that offset. This
+// only works if each color of the variant is in exactly the same pixels
+// as one color of the base, which is verified here, pixel by pixel.
+//
+// Copyright 2023 by Curtis Whitley
+
+use std::collections::HashMap;
+use image::{Rgb, RgbaImage};
+use crate::{FileParameters, pixel_color};
+
+// Remove a leading "./" from a path, so that paths may be compared.
+pub fn normalize_path(path: &str) -> &str {
+    let mut path = path;
+    while let Some(rest) = path.strip_prefix("./") {
+        path = rest;
+    }
+    path
+}
+
+// Verify that a variant has the same pixel structure as its base, and
+// obtain the variant's colors, each with the color index of the base
+// color that it replaces.
+pub fn match_colors(base: &FileParameters, base_img: &RgbaImage,
+                    variant: &FileParameters, variant_img: &RgbaImage) -> Option<HashMap<Rgb<u8>, u8>> {
+    if base.bpp != variant.bpp {
+        println!("ERROR: Variant {} uses {} bits-per-pixel, but its base {} uses {}",
+            variant.path, variant.bpp, base.path, base.bpp);
+        return None;
+    }
+    if base_img.dimensions() != variant_img.dimensions() {
+        println!("ERROR: Variant {} is {}x{} pixels, but its base {} is {}x{}",
+            variant.path, variant_img.width(), variant_img.height(),
+            base.path, base_img.width(), base_img.height());
+        return None;
+    }
+
+    let mut to_variant: HashMap<Rgb<u8>, Rgb<u8>> = HashMap::new();
+    let mut to_base: HashMap<Rgb<u8>, Rgb<u8>> = HashMap::new();
+    for (x, y, base_pixel) in base_img.enumerate_pixels() {
+        let base_color = pixel_color(base_pixel);
+        let variant_color = pixel_color(variant_img.get_pixel(x, y));
+        match (base_color, variant_color) {
+            (None, None) => {},
+            (Some(_), None) => {
+                println!("ERROR: Variant {} is transparent at pixel ({},{}), but its base {} is not",
+                    variant.path, x, y, base.path);
+                return None;
+            },
+            (None, Some(_)) => {
+                println!("ERROR: Variant {} is not transparent at pixel ({},{}), but its base {} is",
+                    variant.path, x, y, base.path);
+                return None;
+            },
+            (Some(b), Some(v)) => {
+                let expected_v = *to_variant.entry(b).or_insert(v);
+                if expected_v != v {
+                    println!("ERROR: Variant {} has color {:x} {:x} {:x} at pixel ({},{}), where its base {} has color {:x} {:x} {:x}, which elsewhere is {:x} {:x} {:x} in the variant",
+                        variant.path, v[0], v[1], v[2], x, y, base.path, b[0], b[1], b[2],
+                        expected_v[0], expected_v[1], expected_v[2]);
+                    return None;
+                }
+                let expected_b = *to_base.entry(v).or_insert(b);
+                if expected_b != b {
+                    println!("ERROR: Variant {} has color {:x} {:x} {:x} at pixel ({},{}), where its base {} has color {:x} {:x} {:x}, but elsewhere the same variant color is {:x} {:x} {:x} in the base",
+                        variant.path, v[0], v[1], v[2], x, y, base.path, b[0], b[1], b[2],
+                        expected_b[0], expected_b[1], expected_b[2]);
+                    return None;
+                }
+            }
+        }
+    }
+
+    // Base colors outside of the colors used for the palette (see '-cc')
+    // do not need an index.
+    let mut colors: HashMap<Rgb<u8>, u8> = HashMap::new();
+    for (base_color, variant_color) in &to_variant {
+        if let Some(index) = base.colors.get(base_color) {
+            colors.insert(*variant_color, *index);
+        }
+    }
+    Some(colors)
+}
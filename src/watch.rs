@@ -0,0 +1,184 @@
+// Watch mode.
+//
+// The input directories and files (including any color cycling ramp
+// images) are polled for changes, apart from the PNG files that the
+// conversion writes itself. Whenever any of them is added, removed, or
+// modified, the whole conversion is run again, and the differences in the
+// palette and in the VRAM memory map, compared to the previous successful
+// run, are listed. Errors do not stop watching, but a
+// failed run may already have written some of its output files.
+//
+// Copyright 2023 by Curtis Whitley
+
+use std::fs;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use crate::cache::Cache;
+use crate::{BuildResult, DirParameters, Options, is_generated_file, run};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// The state of one input file, as last seen.
+#[derive(Debug, Clone, PartialEq)]
+struct FileState {
+    pub path: String,
+    pub modified: Option<SystemTime>,
+    pub length: u64
+}
+
+pub fn watch(directories: &[DirParameters], options: &Options, cache: &mut Option<Cache>) {
+    let mut previous = run(directories, options, cache);
+    let mut snapshot = take_snapshot(directories, options);
+
+    loop {
+        println!("\nWatching for changes (press Ctrl+C to stop)...");
+
+        // Wait for something to change, then wait until the files
+        // stop changing, in case they are still being written.
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let current = take_snapshot(directories, options);
+            if current != snapshot {
+                snapshot = current;
+                break;
+            }
+        }
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let current = take_snapshot(directories, options);
+            if current == snapshot {
+                break;
+            }
+            snapshot = current;
+        }
+
+        println!("\nInput files changed; rebuilding.\n");
+        match run(directories, options, cache) {
+            Some(result) => {
+                if let Some(previous) = &previous {
+                    print_differences(previous, &result);
+                }
+                previous = Some(result);
+            },
+            None => {
+                println!("\nThe rebuild failed; some output files may already have been updated, while");
+                println!("others are left as they were, so fix the problem before using the output.");
+            }
+        }
+    }
+}
+
+// Obtain the states of all input PNG files and the color cycling ramp images,
+// in path order.
+fn take_snapshot(directories: &[DirParameters], options: &Options) -> Vec<FileState> {
+    let mut states: Vec<FileState> = vec![];
+    for path in options.cycles.iter().filter_map(|spec| spec.ramp_path.as_ref()) {
+        states.push(file_state(path));
+    }
+    for directory in directories {
+        if directory.vapor {
+            continue; // no directory or file
+        }
+
+        if directory.path.to_ascii_lowercase().ends_with(".png") {
+            states.push(file_state(&directory.path));
+        } else if let Ok(paths) = fs::read_dir(&directory.path) {
+            for dir_entry in paths.flatten() {
+                let pathname = dir_entry.path().as_os_str().to_string_lossy().to_string();
+                if pathname.to_ascii_lowercase().ends_with(".png") && !is_generated_file(&pathname) {
+                    states.push(file_state(&pathname));
+                }
+            }
+        }
+    }
+    states.sort_by(|a, b| a.path.cmp(&b.path));
+    states
+}
+
+fn file_state(path: &str) -> FileState {
+    match fs::metadata(path) {
+        Ok(metadata) => FileState {
+            path: path.to_string(),
+            modified: metadata.modified().ok(),
+            length: metadata.len()
+        },
+        Err(_) => FileState {
+            path: path.to_string(),
+            modified: None,
+            length: 0
+        }
+    }
+}
+
+// List what changed in the palette and in the VRAM memory map.
+fn print_differences(previous: &BuildResult, current: &BuildResult) {
+    println!("\nChanges since the previous build:");
+    let mut changes = 0;
+
+    for (name, palette_array) in &current.palettes {
+        let previous_array = match previous.palettes.iter().find(|(old_name, _array)| old_name == name) {
+            Some((_name, array)) => array,
+            None => {
+                println!("  added   palette set {}", name);
+                changes += 1;
+                continue;
+            }
+        };
+        let set_text = if name.is_empty() { String::new() } else { format!(" {}", name) };
+        for (index, (old, new)) in previous_array.iter()
+                .zip(palette_array.iter()).enumerate() {
+            if old != new {
+                println!("  palette{} {:03} ${:02x}: {} -> {}",
+                    set_text, index, index, color_text(old), color_text(new));
+                changes += 1;
+            }
+        }
+    }
+
+    for (name, _array) in &previous.palettes {
+        if !current.palettes.iter().any(|(new_name, _array)| new_name == name) {
+            println!("  removed palette set {}", name);
+            changes += 1;
+        }
+    }
+
+    for placement in &current.layout {
+        match previous.layout.iter().find(|p| p.path == placement.path) {
+            Some(old) => {
+                if old.start != placement.start {
+                    println!("  moved   ${:05x} -> ${:05x}  {}",
+                        old.start, placement.start, placement.path);
+                    changes += 1;
+                }
+                if old.size != placement.size {
+                    println!("  resized {:6} -> {:6}  {}",
+                        old.size, placement.size, placement.path);
+                    changes += 1;
+                }
+            },
+            None => {
+                println!("  added   ${:05x} {:5}  {}",
+                    placement.start, placement.size, placement.path);
+                changes += 1;
+            }
+        }
+    }
+
+    for old in &previous.layout {
+        if !current.layout.iter().any(|p| p.path == old.path) {
+            println!("  removed ${:05x} {:5}  {}", old.start, old.size, old.path);
+            changes += 1;
+        }
+    }
+
+    if changes == 0 {
+        println!("  (none)");
+    }
+}
+
+fn color_text(color: &Option<image::Rgb<u8>>) -> String {
+    match color {
+        Some(c) => format!("{:x} {:x} {:x}", c[0], c[1], c[2]),
+        None => "(FREE)".to_string()
+    }
+}
@@ -0,0 +1,57 @@
+// Regression test: running the program twice over the same directory must
+// give the same output, so the files that it writes next to the input
+// images (previews and palette swatches) must not be read back as inputs.
+//
+// Copyright 2023 by Curtis Whitley
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use image::{Rgba, RgbaImage};
+
+fn run_in(dir: &Path, args: &[&str]) {
+    let output = Command::new(env!("CARGO_BIN_EXE_image2binary"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .expect("cannot run image2binary");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(!stdout.contains("ERROR"), "{}", stdout);
+}
+
+// The names and contents of all files in the directory, in name order.
+fn contents_of(dir: &Path) -> Vec<(String, Vec<u8>)> {
+    let mut files: Vec<(String, Vec<u8>)> = fs::read_dir(dir).unwrap()
+        .flatten()
+        .map(|entry| (entry.file_name().to_string_lossy().to_string(), fs::read(entry.path()).unwrap()))
+        .collect();
+    files.sort();
+    files
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("image2binary_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn a_second_run_writes_the_same_files() {
+    let dir = scratch_dir("rerun");
+    let img = RgbaImage::from_fn(8, 8, |x, y| Rgba([x as u8 * 32, y as u8 * 32, 0x80, 0xFF]));
+    img.save(dir.join("seq08.png")).unwrap();
+
+    run_in(&dir, &["-pv", "-e", "png"]);
+    let first = contents_of(&dir);
+    run_in(&dir, &["-pv", "-e", "png"]);
+    let second = contents_of(&dir);
+
+    let names = |files: &[(String, Vec<u8>)]| files.iter().map(|(name, _data)| name.clone()).collect::<Vec<_>>();
+    assert_eq!(names(&first), vec!["PALETTE.BIN", "PALETTE.PNG", "SEQ08.BIN", "SEQ08_PREVIEW.PNG", "seq08.png"]);
+    assert_eq!(names(&first), names(&second));
+    assert!(first == second, "the second run changed the output files");
+
+    fs::remove_dir_all(&dir).unwrap();
+}